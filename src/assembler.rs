@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::symbols::SymbolMap;
+
+// everything an address can be, 64K for XO-CHIP
+const MEMORY_SIZE: usize = 0x10000;
+
+// something wrong with the source, `line` and `column` count from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: u32,
+    pub column: u32,
+    pub message: String,
+}
+
+impl AsmError {
+    pub fn new(line: u32, column: u32, message: impl Into<String>) -> Self {
+        AsmError { line, column, message: message.into() }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+// where in the source a token came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Position {
+    pub fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError::new(self.line, self.column, message)
+    }
+}
+
+// the output of an assembler, `rom` is loaded at `origin`
+#[derive(Debug, Clone)]
+pub struct Assembled {
+    pub origin: u16,
+    pub rom: Vec<u8>,
+    pub symbols: SymbolMap,
+}
+
+// what a reference to a label that isn't defined yet gets patched into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Patch {
+    // the NNN of the instruction at the address
+    Addr12,
+    // a big endian word at the address, for F000 NNNN and data
+    Addr16,
+}
+
+#[derive(Debug, Clone)]
+struct Fixup {
+    addr: u16,
+    patch: Patch,
+    label: String,
+    position: Position,
+}
+
+// memory being assembled into, with the labels and the references waiting
+// for them. the parts of an assembler that don't depend on the syntax
+#[derive(Debug)]
+pub struct Image {
+    memory: Vec<u8>,
+    written: Vec<bool>,
+    here: u32,
+    labels: BTreeMap<String, u16>,
+    fixups: Vec<Fixup>,
+    symbols: SymbolMap,
+    file: String,
+}
+
+impl Image {
+    // `file` is what the source map calls the source
+    pub fn new(file: &str, origin: u16) -> Self {
+        Image {
+            memory: vec![0; MEMORY_SIZE],
+            written: vec![false; MEMORY_SIZE],
+            here: origin as u32,
+            labels: BTreeMap::new(),
+            fixups: Vec::new(),
+            symbols: SymbolMap::new(),
+            file: file.to_string(),
+        }
+    }
+
+    // where the next byte goes
+    pub fn here(&self) -> u16 {
+        self.here as u16
+    }
+
+    pub fn set_here(&mut self, addr: u16) {
+        self.here = addr as u32;
+    }
+
+    // how many bytes have been written so far
+    pub fn written_bytes(&self) -> usize {
+        self.written.iter().filter(|&&written| written).count()
+    }
+
+    // forget what was written from `start` up to `end`, including any
+    // references waiting to be filled in there
+    pub fn erase(&mut self, start: u16, end: u16) {
+        let range = start as usize..end as usize;
+        self.memory[range.clone()].iter_mut().for_each(|b| *b = 0);
+        self.written[range].iter_mut().for_each(|w| *w = false);
+        self.fixups.retain(|fixup| fixup.addr < start || fixup.addr >= end);
+    }
+
+    pub fn byte(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.get(name).copied()
+    }
+
+    pub fn define_label(&mut self, name: &str, position: Position) -> Result<(), AsmError> {
+        if self.labels.contains_key(name) {
+            return Err(position.error(format!("label '{}' is already defined", name)));
+        }
+        self.labels.insert(name.to_string(), self.here());
+        self.symbols.add_label(name, self.here());
+        Ok(())
+    }
+
+    pub fn emit_byte(&mut self, byte: u8, position: Position) -> Result<(), AsmError> {
+        if self.here as usize >= MEMORY_SIZE {
+            return Err(position.error("program doesn't fit in memory"));
+        }
+        self.memory[self.here as usize] = byte;
+        self.written[self.here as usize] = true;
+        self.here += 1;
+        Ok(())
+    }
+
+    // an instruction, which also goes in the source map
+    pub fn emit(&mut self, insn: Instruction, position: Position) -> Result<(), AsmError> {
+        if self.here as usize >= MEMORY_SIZE {
+            return Err(position.error("program doesn't fit in memory"));
+        }
+        let file = self.file.clone();
+        self.symbols.add_line(self.here(), &file, position.line);
+        insn.to_bytes().into_iter().try_for_each(|b| self.emit_byte(b, position))
+    }
+
+    // fill in the address at `addr` once `label` is defined
+    pub fn reference(&mut self, addr: u16, patch: Patch, label: &str, position: Position) {
+        self.fixups.push(Fixup { addr, patch, label: label.to_string(), position });
+    }
+
+    // `insn` with its address filled in once `label` is defined
+    pub fn emit_reference(&mut self, insn: Instruction, label: &str, position: Position) -> Result<(), AsmError> {
+        match insn {
+            Instruction::LdILong(_) => self.reference(self.here().wrapping_add(2), Patch::Addr16, label, position),
+            _ => self.reference(self.here(), Patch::Addr12, label, position),
+        }
+        self.emit(insn, position)
+    }
+
+    // a word of data holding the address of `label`
+    pub fn emit_address(&mut self, label: &str, position: Position) -> Result<(), AsmError> {
+        self.reference(self.here(), Patch::Addr16, label, position);
+        self.emit_byte(0, position)?;
+        self.emit_byte(0, position)
+    }
+
+    // write `target` into what's already at `addr`
+    pub fn patch(&mut self, addr: u16, patch: Patch, target: u16) {
+        let addr = addr as usize;
+        match patch {
+            Patch::Addr12 => {
+                self.memory[addr] = (self.memory[addr] & 0xf0) | (target >> 8 & 0xf) as u8;
+                self.memory[(addr + 1) % MEMORY_SIZE] = target as u8;
+            },
+            Patch::Addr16 => {
+                let [hi, lo] = target.to_be_bytes();
+                self.memory[addr] = hi;
+                self.memory[(addr + 1) % MEMORY_SIZE] = lo;
+            },
+        }
+    }
+
+    // fill in the references and cut out what was written
+    pub fn finish(mut self) -> Result<Assembled, AsmError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let target = self.label(&fixup.label)
+                .ok_or_else(|| fixup.position.error(format!("undefined label '{}'", fixup.label)))?;
+            if fixup.patch == Patch::Addr12 && target > 0xfff {
+                return Err(fixup.position.error(format!(
+                    "label '{}' at {:#06x} is out of reach of a 12 bit address", fixup.label, target,
+                )));
+            }
+            self.patch(fixup.addr, fixup.patch, target);
+        }
+
+        let start = self.written.iter().position(|&w| w).unwrap_or(0);
+        let end = self.written.iter().rposition(|&w| w).map_or(start, |last| last + 1);
+        Ok(Assembled {
+            origin: start as u16,
+            rom: self.memory[start..end].to_vec(),
+            symbols: self.symbols,
+        })
+    }
+}
+
+// what the assemblers' tests share, each front end only needs to test its
+// own syntax
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use crate::disasm::{Disassembly, Syntax};
+
+    pub struct TestAssembler(pub fn(&str, &str) -> Result<Assembled, AsmError>);
+
+    impl TestAssembler {
+        pub fn assemble(&self, source: &str) -> Assembled {
+            self.0(source, "test").unwrap_or_else(|e| panic!("{}", e))
+        }
+
+        pub fn rom(&self, source: &str) -> Vec<u8> {
+            self.assemble(source).rom
+        }
+
+        pub fn error(&self, source: &str) -> AsmError {
+            self.0(source, "test").expect_err("should fail to assemble")
+        }
+
+        // disassembling code and assembling it again should get the same
+        // code back, wherever it's loaded
+        pub fn round_trip(&self, syntax: Syntax) {
+            let bytes = straight_line_code();
+            for base in [0x200, 0x600] {
+                let mut source = Vec::new();
+                Disassembly::new(&bytes, base).write(&mut source, syntax).unwrap();
+                let assembled = self.assemble(&String::from_utf8(source).unwrap());
+                assert_eq!((assembled.origin, assembled.rom), (base, bytes.clone()), "at {:#05x}", base);
+            }
+        }
+    }
+
+    // a sample of every instruction that doesn't end a path, so it's all
+    // disassembled as code, then a long load and an exit
+    pub fn straight_line_code() -> Vec<u8> {
+        let mut bytes: Vec<u8> = (0..=0xffffu16)
+            .step_by(7)
+            .filter_map(|opcode| Instruction::decode(opcode).ok())
+            .filter(|insn| !matches!(insn, Instruction::Jp(_) | Instruction::JpV0(_) | Instruction::Ret | Instruction::Exit))
+            .flat_map(|insn| insn.to_bytes())
+            .collect();
+        bytes.extend([0xf0, 0x00, 0x12, 0x34, 0x00, 0xfd, 0x12, 0x34, 0x56]);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AT: Position = Position { line: 1, column: 1 };
+
+    #[test]
+    fn fixups_are_filled_in() {
+        let mut image = Image::new("test", 0x200);
+        image.emit_reference(Instruction::Jp(0), "end", AT).unwrap();
+        image.emit_reference(Instruction::LdILong(0), "end", AT).unwrap();
+        image.emit_address("end", AT).unwrap();
+        image.define_label("end", AT).unwrap();
+
+        let assembled = image.finish().unwrap();
+        assert_eq!(assembled.origin, 0x200);
+        assert_eq!(assembled.rom, [0x12, 0x08, 0xf0, 0x00, 0x02, 0x08, 0x02, 0x08]);
+    }
+
+    #[test]
+    fn addr12_out_of_reach() {
+        let mut image = Image::new("test", 0x200);
+        let position = Position { line: 3, column: 5 };
+        image.emit_reference(Instruction::Call(0), "far", position).unwrap();
+        image.set_here(0x1000);
+        image.define_label("far", AT).unwrap();
+
+        let error = image.finish().unwrap_err();
+        assert_eq!((error.line, error.column), (3, 5));
+        assert_eq!(error.message, "label 'far' at 0x1000 is out of reach of a 12 bit address");
+    }
+
+    #[test]
+    fn erase_drops_fixups() {
+        let mut image = Image::new("test", 0x200);
+        image.emit_reference(Instruction::Jp(0), "nowhere", AT).unwrap();
+        image.erase(0x200, 0x202);
+        image.emit_byte(0xaa, AT).unwrap();
+
+        let assembled = image.finish().unwrap();
+        assert_eq!((assembled.origin, assembled.rom), (0x202, vec![0xaa]));
+    }
+}
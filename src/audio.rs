@@ -0,0 +1,393 @@
+use std::f32::consts::TAU;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cpu::{CPU, TIMER_HZ};
+use crate::frontend::Frontend;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+// how long the buzzer takes to fade in and out, just long enough that
+// starting and stopping doesn't click
+const RAMP_SECONDS: f32 = 0.005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(format!("unknown waveform '{}'", s)),
+        }
+    }
+}
+
+// the rate XO-CHIP plays the bits of an audio pattern at for a given pitch,
+// in bits per second. the default pitch of 64 gives 4000
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+const PATTERN_BITS: f64 = 128.0;
+
+// the sound played while the sound timer is non-zero, a plain tone or, once
+// an XO-CHIP program has loaded one, the audio pattern
+#[derive(Debug, Clone)]
+pub struct Buzzer {
+    waveform: Waveform,
+    frequency: f32,
+    volume: f32,
+    sample_rate: u32,
+    // how far through the current cycle we are, 0 to 1
+    phase: f32,
+    pattern: Option<([u8; 16], u8)>,
+    // how far through the pattern we are in bits. this carries on from where
+    // it was when the pattern or pitch changes, like the real thing
+    position: f64,
+    // the envelope, ramps between 0 and 1 as the buzzer goes on and off
+    gain: f32,
+}
+
+impl Buzzer {
+    pub fn new(sample_rate: u32) -> Self {
+        Buzzer {
+            waveform: Waveform::Square,
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            sample_rate,
+            phase: 0.0,
+            pattern: None,
+            position: 0.0,
+            gain: 0.0,
+        }
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    // 0 to 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    // play an XO-CHIP audio pattern at `pitch` instead of the tone, or go
+    // back to the tone with None
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        self.pattern = pattern.map(|pattern| (*pattern, pitch));
+    }
+
+    // fill `out` with samples, with the buzzer either on or off
+    pub fn generate(&mut self, on: bool, out: &mut [f32]) {
+        let target = if on { 1.0 } else { 0.0 };
+        let ramp = 1.0 / (RAMP_SECONDS * self.sample_rate as f32);
+        let step = self.frequency / self.sample_rate as f32;
+        let pattern_step = self.pattern.map(|(_, pitch)| pattern_rate(pitch) / self.sample_rate as f64);
+
+        for sample in out {
+            self.gain = if self.gain < target {
+                (self.gain + ramp).min(target)
+            } else {
+                (self.gain - ramp).max(target)
+            };
+
+            let wave = match (&self.pattern, pattern_step) {
+                (Some((bits, _)), Some(pattern_step)) => {
+                    let wave = pattern_average(bits, self.position, pattern_step);
+                    self.position = (self.position + pattern_step) % PATTERN_BITS;
+                    wave
+                },
+                _ => match self.waveform {
+                    Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+                    Waveform::Sine => (self.phase * TAU).sin(),
+                },
+            };
+            *sample = wave * self.gain * self.volume;
+
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+// the pattern averaged over `len` bits starting `from` bits in, with set bits
+// high and clear bits low. averaging over the whole time a sample covers
+// rather than picking the nearest bit keeps high pitches from aliasing
+fn pattern_average(bits: &[u8; 16], from: f64, len: f64) -> f32 {
+    let end = from + len;
+    let mut pos = from;
+    let mut sum = 0.0;
+
+    while pos < end {
+        let bit = pos.floor();
+        let until = (bit + 1.0).min(end);
+        let n = bit as usize % 128;
+        let high = bits[n / 8] & (0x80 >> (n % 8)) != 0;
+        sum += if high { until - pos } else { pos - until };
+        pos = until;
+    }
+
+    (sum / len) as f32
+}
+
+// somewhere to put mono samples between -1 and 1
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// 16 bit mono PCM
+pub struct WavWriter<W: Write + Seek> {
+    writer: Option<hound::WavWriter<W>>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(out: W, sample_rate: u32) -> io::Result<Self> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::new(out, spec).map_err(io::Error::other)?;
+        Ok(WavWriter { writer: Some(writer) })
+    }
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            writer.write_sample(sample).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    // the header can only be filled in once we know how long the file is
+    fn finish(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(writer) => writer.finalize().map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
+}
+
+// wraps another frontend and plays the buzzer into a sink, a frame's worth
+// of samples at a time
+pub struct Audio {
+    inner: Box<dyn Frontend>,
+    buzzer: Buzzer,
+    sink: Box<dyn AudioSink>,
+    sample_rate: u32,
+    frames: u64,
+    buffer: Vec<f32>,
+}
+
+impl Audio {
+    pub fn new(inner: Box<dyn Frontend>, buzzer: Buzzer, sink: Box<dyn AudioSink>, sample_rate: u32) -> Self {
+        Audio { inner, buzzer, sink, sample_rate, frames: 0, buffer: Vec::new() }
+    }
+
+    // frames don't always divide evenly into samples, so count from the
+    // start to keep the leftovers from adding up
+    fn samples_for_frame(&self, frame: u64) -> usize {
+        let start = frame * self.sample_rate as u64 / TIMER_HZ as u64;
+        let end = (frame + 1) * self.sample_rate as u64 / TIMER_HZ as u64;
+        (end - start) as usize
+    }
+}
+
+impl Frontend for Audio {
+    fn poll(&mut self, cpu: &mut CPU) -> io::Result<bool> {
+        self.inner.poll(cpu)
+    }
+
+    fn present(&mut self, cpu: &CPU) -> io::Result<()> {
+        self.inner.present(cpu)?;
+
+        self.buffer.resize(self.samples_for_frame(self.frames), 0.0);
+        self.frames += 1;
+        self.buzzer.set_pattern(cpu.audio_pattern(), cpu.pitch());
+        self.buzzer.generate(cpu.buzzed_last_frame(), &mut self.buffer);
+        self.sink.write(&self.buffer)
+    }
+
+    fn finish(&mut self, cpu: &CPU) -> io::Result<()> {
+        let written = self.sink.finish();
+        self.inner.finish(cpu)?;
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use crate::frontend::Headless;
+
+    // keeps the samples somewhere the test can still get at them
+    #[derive(Debug, Clone, Default)]
+    struct Recorded(Rc<RefCell<Vec<f32>>>);
+
+    impl AudioSink for Recorded {
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            self.0.borrow_mut().extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    // `samples` through a WavWriter and back again
+    fn wav(samples: &[f32], sample_rate: u32) -> (hound::WavSpec, Vec<i16>) {
+        let mut out = Cursor::new(Vec::new());
+        {
+            let mut writer = WavWriter::new(&mut out, sample_rate).unwrap();
+            writer.write(samples).unwrap();
+            writer.finish().unwrap();
+        }
+
+        out.set_position(0);
+        let reader = hound::WavReader::new(out).unwrap();
+        let spec = reader.spec();
+        (spec, reader.into_samples().map(Result::unwrap).collect())
+    }
+
+    // 4 samples high then 4 low, ramping up over the first 40
+    fn assert_square(samples: &[i16]) {
+        for (n, &sample) in samples.iter().enumerate() {
+            let level = i16::MAX as f32 * ((n + 1) as f32 / 40.0).min(1.0);
+            let expected = if n % 8 < 4 { level } else { -level };
+            assert!((sample as f32 - expected).abs() <= 1.0, "sample {} is {}, not {}", n, sample, expected);
+        }
+    }
+
+    #[test]
+    fn square_tone_to_wav() {
+        let mut buzzer = Buzzer::new(8000);
+        buzzer.set_frequency(1000.0);
+        buzzer.set_volume(1.0);
+        let mut samples = vec![0.0; 80];
+        buzzer.generate(true, &mut samples);
+
+        let (spec, samples) = wav(&samples, 8000);
+        assert_eq!((spec.channels, spec.sample_rate, spec.bits_per_sample), (1, 8000, 16));
+        assert_eq!(samples.len(), 80);
+        assert_eq!(samples[..4], [819, 1638, 2457, 3276]);
+        assert_square(&samples);
+    }
+
+    #[test]
+    fn off_ramps_down_to_silence() {
+        let mut buzzer = Buzzer::new(8000);
+        buzzer.set_waveform(Waveform::Sine);
+        let mut samples = vec![0.0; 100];
+        buzzer.generate(true, &mut samples);
+        assert!(samples.iter().any(|&sample| sample > 0.2));
+
+        buzzer.generate(false, &mut samples);
+        assert!(samples[0].abs() > 0.0);
+        assert!(samples[40..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn every_second_gets_every_sample() {
+        let audio = Audio::new(Box::new(Headless::default()), Buzzer::new(44100), Box::new(Recorded::default()), 44100);
+        let counts: Vec<usize> = (0..60).map(|frame| audio.samples_for_frame(frame)).collect();
+        assert_eq!(counts.iter().sum::<usize>(), 44100);
+        assert!(counts.iter().all(|&count| count == 735));
+    }
+
+    #[test]
+    fn pattern_rates() {
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert_eq!(pattern_rate(112), 8000.0);
+        assert_eq!(pattern_rate(16), 2000.0);
+    }
+
+    #[test]
+    fn pattern_to_wav() {
+        // at pitch 112 and 8000 Hz each sample is one bit of the pattern
+        let mut buzzer = Buzzer::new(8000);
+        buzzer.set_volume(1.0);
+        buzzer.set_pattern(Some(&[0xf0; 16]), 112);
+        let mut samples = vec![0.0; 256];
+        buzzer.generate(true, &mut samples);
+
+        let (_, samples) = wav(&samples, 8000);
+        assert_eq!(samples.len(), 256);
+        assert_eq!(samples[..4], [819, 1638, 2457, 3276]);
+        assert_square(&samples);
+
+        // a lower pitch takes more than one sample a bit, picking up where
+        // the last pattern left off
+        buzzer.set_pattern(Some(&[0xcc; 16]), 64);
+        let mut samples = vec![0.0; 8];
+        buzzer.generate(true, &mut samples);
+        assert_eq!(samples, [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+
+        // samples that cover an edge get the average
+        assert_eq!(pattern_average(&[0xf0; 16], 3.5, 1.0), 0.0);
+        assert_eq!(pattern_average(&[0xf0; 16], 3.0, 4.0), -0.5);
+        assert_eq!(pattern_average(&[0x01; 16], 127.0, 2.0), 0.0);
+    }
+
+    #[test]
+    fn cpu_pattern_and_pitch() {
+        let mut cpu = CPU::new();
+        let rom = [
+            0xa3, 0x00, // i := 0x300
+            0xf0, 0x02, // audio
+            0x60, 0x70, // v0 := 112
+            0xf0, 0x3a, // pitch := v0
+            0x60, 0x02, // v0 := 2
+            0xf0, 0x18, // buzzer := v0
+            0x12, 0x0c, // loop forever
+        ];
+        cpu.load_rom(0x200, &rom).unwrap();
+        cpu.write_bytes(0x300, &[0xf0; 16]).unwrap();
+
+        let recorded = Recorded::default();
+        let mut buzzer = Buzzer::new(8000);
+        buzzer.set_volume(1.0);
+        let mut audio = Audio::new(Box::new(Headless::default()), buzzer, Box::new(recorded.clone()), 8000);
+        for _ in 0..3 {
+            cpu.run_frame().unwrap();
+            audio.present(&cpu).unwrap();
+        }
+
+        // two frames of the pattern then it fades out
+        let samples = recorded.0.borrow();
+        assert_eq!(samples.len(), 133 + 133 + 134);
+        assert_eq!(samples[40..48], [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+        assert!(samples[266 + 40..].iter().all(|&sample| sample == 0.0));
+    }
+}
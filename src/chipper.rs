@@ -0,0 +1,699 @@
+use std::collections::HashMap;
+
+use crate::assembler::{AsmError, Assembled, Image, Position};
+use crate::instruction::Instruction;
+
+// where programs start unless there's an ORG
+const ORIGIN: u16 = 0x200;
+
+// passes to try for every symbol to settle before the last one
+const MAX_PASSES: u32 = 8;
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SCD", "SCU", "PLANE", "SYS", "CALL", "JP", "SE",
+    "SNE", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "SAVE", "LOAD", "LD",
+];
+
+// the instructions an OPTION allows, each set includes the ones before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum OpcodeSet {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+impl OpcodeSet {
+    fn from_option(name: &str) -> Option<Self> {
+        match name {
+            "CHIP8" | "CHIP48" => Some(OpcodeSet::Chip8),
+            "SCHIP" | "SCHIP10" | "SCHIP11" | "SUPERCHIP" => Some(OpcodeSet::Schip),
+            "XOCHIP" => Some(OpcodeSet::XoChip),
+            _ => None,
+        }
+    }
+
+    // the smallest set with `insn` in it
+    fn needed_for(insn: &Instruction) -> Self {
+        use Instruction::*;
+
+        match insn {
+            ScrollDown(_) | ScrollRight | ScrollLeft | Exit | Lores | Hires | LdBigFont(_) | SaveFlags(_)
+            | LoadFlags(_) => OpcodeSet::Schip,
+            ScrollUp(_) | SaveRange(..) | LoadRange(..) | LdILong(_) | Plane(_) | Audio | Pitch(_) => {
+                OpcodeSet::XoChip
+            },
+            _ => OpcodeSet::Chip8,
+        }
+    }
+}
+
+// one comma separated operand and where it starts
+#[derive(Debug, Clone)]
+struct Operand {
+    text: String,
+    position: Position,
+}
+
+impl Operand {
+    fn upper(&self) -> String {
+        self.text.to_ascii_uppercase()
+    }
+
+    fn register(&self) -> Option<u8> {
+        let upper = self.upper();
+        let digit = upper.strip_prefix('V')?;
+        match digit.len() {
+            1 => u8::from_str_radix(digit, 16).ok(),
+            _ => None,
+        }
+    }
+}
+
+// the part of a line before any ; comment, which can't start inside a DA
+// string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {},
+        }
+    }
+    line
+}
+
+// split on top level commas, keeping track of the column each piece starts at
+fn split_operands(text: &str, offset: usize, line: &str, number: u32) -> Vec<Operand> {
+    let column = |i: usize| line[..offset + i].chars().count() as u32 + 1;
+    let mut operands = Vec::new();
+    let mut push = |piece: &str, start: usize| {
+        let trimmed = piece.trim_start();
+        let start = start + piece.len() - trimmed.len();
+        operands.push(Operand {
+            text: trimmed.trim_end().to_string(),
+            position: Position { line: number, column: column(start) },
+        });
+    };
+
+    let (mut depth, mut quoted, mut start) = (0, false, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                push(&text[start..i], start);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    if !text.trim().is_empty() {
+        push(&text[start..], start);
+    }
+
+    operands
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+struct Assembler {
+    image: Image,
+    // labels and EQUs defined so far this pass
+    symbols: HashMap<String, i64>,
+    // and all of them from the pass before, for using them before they're
+    // defined
+    previous: HashMap<String, i64>,
+    // whether this is the pass that counts, with every symbol settled
+    last_pass: bool,
+    align: bool,
+    set: OpcodeSet,
+    // labels waiting for whatever comes next, so they end up after any
+    // padding ALIGN puts before an instruction
+    pending: Vec<(String, Position)>,
+}
+
+// assemble Chipper source. `file` is what the source map calls it
+pub fn assemble(source: &str, file: &str) -> Result<Assembled, AsmError> {
+    let mut assembler = Assembler {
+        image: Image::new(file, ORIGIN),
+        symbols: HashMap::new(),
+        previous: HashMap::new(),
+        last_pass: false,
+        align: true,
+        set: OpcodeSet::XoChip,
+        pending: Vec::new(),
+    };
+
+    // an EQU or ORG can use a label that's further on, which can move
+    // other labels, so go again until nothing changes
+    for _ in 0..MAX_PASSES {
+        assembler.pass(source, file)?;
+        let symbols = std::mem::take(&mut assembler.symbols);
+        let settled = symbols == assembler.previous;
+        assembler.previous = symbols;
+        if settled {
+            break;
+        }
+    }
+
+    assembler.last_pass = true;
+    assembler.pass(source, file)?;
+    assembler.image.finish()
+}
+
+impl Assembler {
+    fn pass(&mut self, source: &str, file: &str) -> Result<(), AsmError> {
+        self.image = Image::new(file, ORIGIN);
+        self.align = true;
+        self.set = OpcodeSet::XoChip;
+
+        for (n, line) in source.lines().enumerate() {
+            if !self.line(n as u32 + 1, line)? {
+                break;
+            }
+        }
+        self.place_labels()
+    }
+
+    // returns false at END
+    fn line(&mut self, number: u32, line: &str) -> Result<bool, AsmError> {
+        let code = strip_comment(line);
+        // every piece is a slice of `line`, so where it starts is how far in
+        // its pointer is
+        let offset = |part: &str| part.as_ptr() as usize - line.as_ptr() as usize;
+        let column = |rest: &str| line[..offset(rest)].chars().count() as u32 + 1;
+        let position = |rest: &str| Position { line: number, column: column(rest) };
+        let mut rest = code.trim_start();
+
+        // label:
+        if let Some((name, after)) = rest.split_once(':') {
+            if is_identifier(name) {
+                self.pending.push((name.to_string(), position(rest)));
+                rest = after.trim_start();
+            }
+        }
+        if rest.is_empty() {
+            return Ok(true);
+        }
+
+        let (word, after) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+        let at = position(rest);
+        let after = after.trim_start();
+
+        // name EQU value, or name = value
+        let (second, value) = after.split_at(after.find(char::is_whitespace).unwrap_or(after.len()));
+        if second.eq_ignore_ascii_case("EQU") || second == "=" {
+            if !is_identifier(word) {
+                return Err(at.error(format!("'{}' can't be used as a name", word)));
+            }
+            let operands = split_operands(value, offset(value), line, number);
+            let value = self.expression(self.single(&operands, at)?)?;
+            return self.define(word, value, false, at).map(|_| true);
+        }
+
+        let operands = split_operands(after, offset(after), line, number);
+        let mnemonic = word.to_ascii_uppercase();
+        match mnemonic.as_str() {
+            "END" => return Ok(false),
+            "ORG" => {
+                self.place_labels()?;
+                let addr = self.number(self.single(&operands, at)?, 0xffff)?;
+                self.image.set_here(addr as u16);
+            },
+            "ALIGN" => match operands.first().map(Operand::upper).as_deref() {
+                Some("ON") => self.align = true,
+                Some("OFF") => self.align = false,
+                None => {
+                    self.pad(at)?;
+                    self.place_labels()?;
+                },
+                Some(_) => return Err(operands[0].position.error("ALIGN takes ON or OFF")),
+            },
+            "OPTION" => {
+                let operand = self.single(&operands, at)?;
+                let name = operand.upper().replace('-', "");
+                self.set = OpcodeSet::from_option(&name)
+                    .ok_or_else(|| operand.position.error(format!("unknown option '{}'", operand.text)))?;
+            },
+            "DB" => {
+                self.place_labels()?;
+                for operand in &operands {
+                    let value = self.byte(operand)?;
+                    self.image.emit_byte(value, operand.position)?;
+                }
+            },
+            "DW" => {
+                self.place_labels()?;
+                for operand in &operands {
+                    let value = self.number(operand, 0xffff)? as u16;
+                    value.to_be_bytes().iter().try_for_each(|&b| self.image.emit_byte(b, operand.position))?;
+                }
+            },
+            "DA" => {
+                self.place_labels()?;
+                for operand in &operands {
+                    let text = operand.text.strip_prefix('\'').and_then(|text| text.strip_suffix('\''))
+                        .ok_or_else(|| operand.position.error("DA takes a string in single quotes"))?;
+                    text.bytes().try_for_each(|b| self.image.emit_byte(b, operand.position))?;
+                }
+            },
+            "DS" => {
+                self.place_labels()?;
+                let count = self.number(self.single(&operands, at)?, 0xffff)?;
+                (0..count).try_for_each(|_| self.image.emit_byte(0, at))?;
+            },
+            _ => {
+                let insn = self.instruction(&mnemonic, &operands, at)?;
+                if OpcodeSet::needed_for(&insn) > self.set {
+                    return Err(at.error(format!("{} isn't in the instruction set chosen by OPTION", mnemonic)));
+                }
+                if self.align {
+                    self.pad(at)?;
+                }
+                self.place_labels()?;
+                self.image.emit(insn, at)?;
+            },
+        }
+
+        Ok(true)
+    }
+
+    // a zero byte if needed to get to an even address
+    fn pad(&mut self, at: Position) -> Result<(), AsmError> {
+        if !self.image.here().is_multiple_of(2) {
+            self.image.emit_byte(0, at)?;
+        }
+        Ok(())
+    }
+
+    fn place_labels(&mut self) -> Result<(), AsmError> {
+        let here = self.image.here() as i64;
+        for (name, position) in std::mem::take(&mut self.pending) {
+            self.define(&name, here, true, position)?;
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: i64, label: bool, position: Position) -> Result<(), AsmError> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(position.error(format!("'{}' is already defined", name)));
+        }
+        if !self.last_pass {
+            return Ok(());
+        }
+
+        // anything used before it was defined got the value from the pass
+        // before, which has to be the same
+        if self.previous.get(name) != Some(&value) {
+            return Err(position.error(if label {
+                format!("'{}' moved between passes, does an ORG or DS before it use a later label?", name)
+            } else {
+                format!("'{}' changes every pass, does it depend on itself?", name)
+            }));
+        }
+        if label {
+            self.image.define_label(name, position)?;
+        }
+        Ok(())
+    }
+
+    fn symbol(&self, name: &str) -> Option<i64> {
+        self.symbols.get(name).or_else(|| self.previous.get(name)).copied()
+    }
+
+    fn single<'a>(&self, operands: &'a [Operand], at: Position) -> Result<&'a Operand, AsmError> {
+        match operands {
+            [operand] => Ok(operand),
+            _ => Err(at.error(format!("expected 1 operand, found {}", operands.len()))),
+        }
+    }
+
+    fn register(&self, operand: &Operand) -> Result<u8, AsmError> {
+        operand.register()
+            .ok_or_else(|| operand.position.error(format!("expected a register, found '{}'", operand.text)))
+    }
+
+    // an expression that has to be between 0 and `max`. symbols can be
+    // wrong until the last pass, so it's only checked then
+    fn number(&self, operand: &Operand, max: i64) -> Result<i64, AsmError> {
+        let value = self.expression(operand)?;
+        if !self.last_pass {
+            return Ok(value.clamp(0, max));
+        }
+        if !(0..=max).contains(&value) {
+            return Err(operand.position.error(format!("{:#x} is out of range", value)));
+        }
+        Ok(value)
+    }
+
+    fn byte(&self, operand: &Operand) -> Result<u8, AsmError> {
+        let value = self.expression(operand)?;
+        if self.last_pass && !(-128..=255).contains(&value) {
+            return Err(operand.position.error(format!("{} doesn't fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand], at: Position) -> Result<Instruction, AsmError> {
+        use Instruction::*;
+
+        let reg = |i: usize| self.register(&operands[i]);
+        let byte = |i: usize| self.byte(&operands[i]);
+        let nibble = |i: usize| self.number(&operands[i], 0xf).map(|n| n as u8);
+        let addr = |i: usize| self.number(&operands[i], 0xfff).map(|n| n as u16);
+        let is_reg = |i: usize| operands[i].register().is_some();
+        let names: Vec<String> = operands.iter().map(Operand::upper).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        Ok(match (mnemonic, operands.len()) {
+            ("CLS", 0) => Cls,
+            ("RET", 0) => Ret,
+            ("SCR", 0) => ScrollRight,
+            ("SCL", 0) => ScrollLeft,
+            ("EXIT", 0) => Exit,
+            ("LOW", 0) => Lores,
+            ("HIGH", 0) => Hires,
+            ("AUDIO", 0) => Audio,
+            ("SCD", 1) => ScrollDown(nibble(0)?),
+            ("SCU", 1) => ScrollUp(nibble(0)?),
+            ("PLANE", 1) => Plane(nibble(0)?),
+            ("SYS", 1) => Sys(addr(0)?),
+            ("CALL", 1) => Call(addr(0)?),
+            ("JP", 1) => Jp(addr(0)?),
+            ("JP", 2) if names[0] == "V0" => JpV0(addr(1)?),
+            ("SE", 2) if is_reg(1) => SeReg(reg(0)?, reg(1)?),
+            ("SE", 2) => Se(reg(0)?, byte(1)?),
+            ("SNE", 2) if is_reg(1) => SneReg(reg(0)?, reg(1)?),
+            ("SNE", 2) => Sne(reg(0)?, byte(1)?),
+            ("ADD", 2) if names[0] == "I" => AddI(reg(1)?),
+            ("ADD", 2) if is_reg(1) => AddReg(reg(0)?, reg(1)?),
+            ("ADD", 2) => Add(reg(0)?, byte(1)?),
+            ("OR", 2) => Or(reg(0)?, reg(1)?),
+            ("AND", 2) => And(reg(0)?, reg(1)?),
+            ("XOR", 2) => Xor(reg(0)?, reg(1)?),
+            ("SUB", 2) => Sub(reg(0)?, reg(1)?),
+            ("SUBN", 2) => Subn(reg(0)?, reg(1)?),
+            // without a VY, shift VX into itself so it's the same on every
+            // interpreter
+            ("SHR", 1) => Shr(reg(0)?, reg(0)?),
+            ("SHR", 2) => Shr(reg(0)?, reg(1)?),
+            ("SHL", 1) => Shl(reg(0)?, reg(0)?),
+            ("SHL", 2) => Shl(reg(0)?, reg(1)?),
+            ("RND", 2) => Rnd(reg(0)?, byte(1)?),
+            ("DRW", 3) => Drw(reg(0)?, reg(1)?, nibble(2)?),
+            ("SKP", 1) => Skp(reg(0)?),
+            ("SKNP", 1) => Sknp(reg(0)?),
+            ("SAVE", 2) => SaveRange(reg(0)?, reg(1)?),
+            ("LOAD", 2) => LoadRange(reg(0)?, reg(1)?),
+            ("LD", 2) => match (names[0], names[1]) {
+                ("I", long) if long.strip_prefix("LONG").is_some_and(|rest| rest.starts_with(char::is_whitespace)) => {
+                    let operand = &operands[1];
+                    let text = operand.text[4..].trim_start();
+                    let column = operand.position.column + (operand.text.len() - text.len()) as u32;
+                    let target = Operand { text: text.to_string(), position: Position { column, ..operand.position } };
+                    LdILong(self.number(&target, 0xffff)? as u16)
+                },
+                ("I", _) => LdI(addr(1)?),
+                ("DT", _) => SetDelay(reg(1)?),
+                ("ST", _) => SetSound(reg(1)?),
+                ("F", _) => LdFont(reg(1)?),
+                ("HF", _) => LdBigFont(reg(1)?),
+                ("B", _) => Bcd(reg(1)?),
+                ("[I]", _) => StoreRegs(reg(1)?),
+                ("R", _) => SaveFlags(reg(1)?),
+                ("PITCH", _) => Pitch(reg(1)?),
+                (_, "DT") => GetDelay(reg(0)?),
+                (_, "K") => WaitKey(reg(0)?),
+                (_, "[I]") => LoadRegs(reg(0)?),
+                (_, "R") => LoadFlags(reg(0)?),
+                _ if is_reg(1) => LdReg(reg(0)?, reg(1)?),
+                _ => Ld(reg(0)?, byte(1)?),
+            },
+            (_, n) if MNEMONICS.contains(&mnemonic) => {
+                return Err(at.error(format!("wrong number of operands for {}, found {}", mnemonic, n)));
+            },
+            _ => return Err(at.error(format!("unknown instruction '{}'", mnemonic))),
+        })
+    }
+
+    fn expression(&self, operand: &Operand) -> Result<i64, AsmError> {
+        let mut parser = Expression { assembler: self, operand, chars: operand.text.char_indices().collect(), i: 0 };
+        let value = parser.or()?;
+        parser.skip_spaces();
+        match parser.chars.get(parser.i) {
+            Some(&(_, c)) => Err(parser.error(format!("unexpected '{}' in expression", c))),
+            None => Ok(value),
+        }
+    }
+}
+
+// the usual precedence, from | binding loosest down to unary operators
+struct Expression<'a> {
+    assembler: &'a Assembler,
+    operand: &'a Operand,
+    chars: Vec<(usize, char)>,
+    i: usize,
+}
+
+impl Expression<'_> {
+    fn error(&self, message: String) -> AsmError {
+        let column = self.operand.position.column + self.i.min(self.chars.len()) as u32;
+        AsmError::new(self.operand.position.line, column, message)
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.chars.get(self.i).is_some_and(|(_, c)| c.is_whitespace()) {
+            self.i += 1;
+        }
+    }
+
+    // the operator coming up if it's one of `ops`, longest first
+    fn operator(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        self.skip_spaces();
+        let rest: String = self.chars[self.i..].iter().map(|(_, c)| c).collect();
+        let op = ops.iter().find(|op| rest.starts_with(*op))?;
+        self.i += op.len();
+        Some(op)
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&'static str],
+        next: fn(&mut Self) -> Result<i64, AsmError>,
+    ) -> Result<i64, AsmError> {
+        let mut value = next(self)?;
+        while let Some(op) = self.operator(ops) {
+            let right = next(self)?;
+            value = match op {
+                "|" => value | right,
+                "^" => value ^ right,
+                "&" => value & right,
+                "<<" => value.checked_shl(right as u32).unwrap_or(0),
+                ">>" => value.checked_shr(right as u32).unwrap_or(0),
+                "+" => value.wrapping_add(right),
+                "-" => value.wrapping_sub(right),
+                "*" => value.wrapping_mul(right),
+                _ if right == 0 && !self.assembler.last_pass => 0,
+                _ if right == 0 => return Err(self.error("division by zero".to_string())),
+                "/" => value / right,
+                _ => value % right,
+            };
+        }
+        Ok(value)
+    }
+
+    fn or(&mut self) -> Result<i64, AsmError> {
+        self.binary(&["|"], Self::xor)
+    }
+
+    fn xor(&mut self) -> Result<i64, AsmError> {
+        self.binary(&["^"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<i64, AsmError> {
+        self.binary(&["&"], Self::shift)
+    }
+
+    fn shift(&mut self) -> Result<i64, AsmError> {
+        self.binary(&["<<", ">>"], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<i64, AsmError> {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<i64, AsmError> {
+        self.binary(&["*", "/", "%"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<i64, AsmError> {
+        match self.operator(&["-", "~", "+"]) {
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some("~") => Ok(!self.unary()?),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, AsmError> {
+        self.skip_spaces();
+        if self.operator(&["("]).is_some() {
+            let value = self.or()?;
+            return match self.operator(&[")"]) {
+                Some(_) => Ok(value),
+                None => Err(self.error("( without a )".to_string())),
+            };
+        }
+
+        let start = self.i;
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || "#$@".contains(c);
+        while self.chars.get(self.i).is_some_and(|&(_, c)| is_word(c)) {
+            self.i += 1;
+        }
+        let word: String = self.chars[start..self.i].iter().map(|(_, c)| c).collect();
+        if word.is_empty() {
+            return Err(self.error("expected a value".to_string()));
+        }
+
+        let radix = |digits: &str, radix| i64::from_str_radix(digits, radix).ok();
+        let value = if let Some(hex) = word.strip_prefix('#').or_else(|| word.strip_prefix("0x")) {
+            radix(hex, 16)
+        } else if let Some(binary) = word.strip_prefix('$') {
+            // sprites are often drawn with . for 0
+            radix(&binary.replace('.', "0"), 2)
+        } else if let Some(octal) = word.strip_prefix('@') {
+            radix(octal, 8)
+        } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+            radix(&word, 10)
+        } else if is_identifier(&word) {
+            match self.assembler.symbol(&word) {
+                Some(value) => Some(value),
+                // the first pass only needs sizes, which don't depend on
+                // what symbols are, and later ones try again
+                None if !self.assembler.last_pass => Some(0),
+                None => {
+                    self.i = start;
+                    return Err(self.error(format!("undefined symbol '{}'", word)));
+                },
+            }
+        } else {
+            None
+        };
+
+        value.ok_or_else(|| {
+            self.i = start;
+            self.error(format!("invalid number '{}'", word))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::testing::TestAssembler;
+    use crate::disasm::Syntax;
+
+    const CHIPPER: TestAssembler = TestAssembler(assemble);
+
+    #[test]
+    fn instructions() {
+        let source = "
+            LD V0, #12
+            DRW V1, V2, 5
+            ld i, LONG #1234
+            LD I,LONG\tlabel
+            SHR V3
+            LD [I], VF
+label:  CLS
+        ";
+        assert_eq!(
+            CHIPPER.rom(source),
+            [0x60, 0x12, 0xd1, 0x25, 0xf0, 0x00, 0x12, 0x34, 0xf0, 0x00, 0x02, 0x10, 0x83, 0x36, 0xff, 0x55, 0x00, 0xe0],
+        );
+        assert_eq!(CHIPPER.error("  FOO V1"), AsmError::new(1, 3, "unknown instruction 'FOO'"));
+        assert_eq!(CHIPPER.error("  LD V0"), AsmError::new(1, 3, "wrong number of operands for LD, found 1"));
+    }
+
+    #[test]
+    fn option_limits_the_instruction_set() {
+        assert_eq!(CHIPPER.rom("  OPTION SCHIP11\n  HIGH"), [0x00, 0xff]);
+        assert_eq!(CHIPPER.rom("  OPTION XO-CHIP\n  PLANE 3"), [0xf3, 0x01]);
+        assert_eq!(
+            CHIPPER.error("  OPTION CHIP8\n  HIGH"),
+            AsmError::new(2, 3, "HIGH isn't in the instruction set chosen by OPTION"),
+        );
+        assert_eq!(
+            CHIPPER.error("  OPTION SCHIP\n  LD I, LONG 0"),
+            AsmError::new(2, 3, "LD isn't in the instruction set chosen by OPTION"),
+        );
+        assert_eq!(CHIPPER.error("  OPTION Z80").message, "unknown option 'Z80'");
+    }
+
+    #[test]
+    fn align() {
+        // labels go after the padding, with the instruction they're for
+        let assembled = CHIPPER.assemble("  DB 1\nhere: CLS");
+        assert_eq!(assembled.rom, [0x01, 0x00, 0x00, 0xe0]);
+        assert_eq!(assembled.symbols.label("here"), Some(0x202));
+
+        assert_eq!(CHIPPER.rom("  ALIGN OFF\n  DB 1\n  CLS"), [0x01, 0x00, 0xe0]);
+        assert_eq!(CHIPPER.rom("  ALIGN OFF\n  DB 1\n  ALIGN\n  DB 2"), [0x01, 0x00, 0x02]);
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(CHIPPER.rom("  DB 1, -1, #FF"), [0x01, 0xff, 0xff]);
+        assert_eq!(CHIPPER.rom("start: DW #1234, start"), [0x12, 0x34, 0x02, 0x00]);
+        assert_eq!(CHIPPER.rom("  DA 'Hi; there'"), b"Hi; there");
+        assert_eq!(CHIPPER.rom("  DS 3\n  DB 9"), [0, 0, 0, 9]);
+        assert_eq!(CHIPPER.error("  DB 256"), AsmError::new(1, 6, "256 doesn't fit in a byte"));
+    }
+
+    #[test]
+    fn equ() {
+        let source = "
+SPEED   EQU 4
+HALF    = SPEED / 2 + 1
+        LD V0, HALF * 2
+        LD V1, (SPEED + 1) << 1
+        ";
+        assert_eq!(CHIPPER.rom(source), [0x60, 0x06, 0x61, 0x0a]);
+        assert_eq!(CHIPPER.error("A EQU 1\nA = 2"), AsmError::new(2, 1, "'A' is already defined"));
+    }
+
+    #[test]
+    fn equ_of_a_later_label() {
+        // X is used before it's defined, and defined using a label after that
+        let source = "  LD V0, X\nX EQU later - #200\n  DS 4\nlater: CLS";
+        assert_eq!(CHIPPER.rom(source), [0x60, 0x06, 0, 0, 0, 0, 0x00, 0xe0]);
+        assert_eq!(
+            CHIPPER.error("A EQU B + 1\nB EQU A + 1\n  DB A"),
+            AsmError::new(1, 1, "'A' changes every pass, does it depend on itself?"),
+        );
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(CHIPPER.rom("  DB #1F, $.1111..., $01, @17, 17, 0x1f"), [0x1f, 0x78, 0x01, 0x0f, 0x11, 0x1f]);
+        assert_eq!(CHIPPER.error("  DB #G"), AsmError::new(1, 6, "invalid number '#G'"));
+    }
+
+    #[test]
+    fn forward_references() {
+        assert_eq!(CHIPPER.rom("  JP end\n  LD I, data + 1\nend: RET\ndata: DB 1, 2"), [
+            0x12, 0x04, 0xa2, 0x07, 0x00, 0xee, 0x01, 0x02,
+        ]);
+        assert_eq!(CHIPPER.error("  JP nowhere"), AsmError::new(1, 6, "undefined symbol 'nowhere'"));
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        CHIPPER.round_trip(Syntax::Classic);
+    }
+}
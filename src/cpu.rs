@@ -1,73 +1,222 @@
-use std::cmp;
 use std::convert::TryInto;
-use std::iter::Iterator;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::display::Display;
+use crate::flags::{FlagStorage, MemoryFlagStorage};
+use crate::font::{self, BigFont, Font};
+use crate::instruction::Instruction;
+use crate::keypad::Keypad;
+use crate::quirks::Quirks;
+use crate::stack::{self, Stack, StackDepth};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    // `opcode` at `addr` isn't an instruction we know about
+    InvalidOpcode { addr: u16, opcode: u16 },
+    StackOverflow,
+    StackUnderflow,
+    // an access of `len` bytes from `addr` ran past the end of memory
+    MemoryOutOfBounds { addr: u16, len: usize },
+    // FX75/FX85 couldn't get at the flag storage
+    FlagStorage(io::ErrorKind),
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::InvalidOpcode { addr, opcode } =>
+                write!(f, "invalid opcode {:04x} at {:#05x}", opcode, addr),
+            CpuError::StackOverflow => write!(f, "stack overflow"),
+            CpuError::StackUnderflow => write!(f, "stack underflow"),
+            CpuError::MemoryOutOfBounds { addr, len } =>
+                write!(f, "memory access out of bounds: {:#05x}..{:#05x}", addr, *addr as usize + len),
+            CpuError::FlagStorage(kind) => write!(f, "flag storage: {}", kind),
+        }
+    }
+}
+
+impl Error for CpuError {}
+
+impl From<io::Error> for CpuError {
+    fn from(e: io::Error) -> Self {
+        CpuError::FlagStorage(e.kind())
+    }
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Registers {
     v_regs: [u8; 16],
     i: u16,
     pc: u16,
-    sp: u16, // this can be 8 bit?
+}
+
+// an in-progress FX0A. like the COSMAC VIP we wait for a key to be pressed
+// and then released before storing it and carrying on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyWait {
+    reg: u8,
+    key: Option<u8>,
 }
 
 #[derive(Debug)]
 pub struct CPU {
     regs: Registers, 
     memory: Vec<u8>,
+    stack: Stack,
+    // mirror the stack into memory VIP style, for ROMs that go looking for it
+    memory_mapped_stack: bool,
+    display: Display,
+    keypad: Keypad,
+    key_wait: Option<KeyWait>,
+    font_base: u16,
+    big_font_base: u16,
+    // SUPER-CHIP's RPL user flags
+    flags: Box<dyn FlagStorage>,
+    // set by 00FD, nothing more gets executed
+    exited: bool,
+    // XO-CHIP audio, the 128 bit sample loaded by F002 and the FX3A pitch
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    quirks: Quirks,
+    // set by DXYN under the display wait quirk to end the current frame
+    vblank_wait: bool,
     // "pseudo registers"
     dt: u8, // delay timer
     st: u8, // sound timer,
-    timedelta_error: u32, // the number of ms not used in the previous cycle(s)
+    // whether the buzzer was on for the frame run_frame last ran
+    buzzed: bool,
+    instructions_per_frame: u32,
     prng_val: u32,
 }
 
-fn bytes_to_nibbles<'a>(bytes: impl Iterator<Item = &'a u8>) -> Vec<u8> {
-    let mut v = Vec::new();
-    for b in bytes {
-        v.push((b & 0xf0) >> 4);
-        v.push(b & 0x0f);
-    }
-    v
-}
+// where programs normally get loaded, and where ETI-660 programs get loaded
+pub const PROGRAM_START: u16 = 0x200;
 
-pub fn nibbles_to_bytes<'a>(nibbles: impl Iterator<Item = &'a u8>) -> Vec<u8> {
-    let mut v: Vec<u8> = Vec::new();
-    let mut first_half = true;
+pub const MEMORY_SIZE: usize = 4096;
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+pub const ETI_660_PROGRAM_START: u16 = 0x600;
 
-    for b in nibbles {
-        if first_half {
-            v.push(*b);
-        } else {
-            let idx = v.len() - 1;
-            v[idx] = v[idx] << 4 | *b;
-        }
-        first_half = !first_half;
-    }
+// 4000Hz playback of the audio pattern
+pub const DEFAULT_PITCH: u8 = 64;
 
-    v
-}
+// DT and ST count down at this rate, and a "frame" is one tick of them
+pub const TIMER_HZ: u32 = 60;
+// roughly the speed of the original interpreter on the VIP
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
 
-fn nibbles3_to_u16(insn_nibbles: Vec<u8>) -> u16 {
-    u16::from_be_bytes(
-        nibbles_to_bytes([0u8].iter().chain(insn_nibbles[1..].iter()))
-    .try_into().unwrap())
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
     pub fn new() -> Self {
-        CPU {
+        let mut cpu = CPU {
             regs: Registers {
                 v_regs: [0; 16],
                 i: 0,
                 pc: 0,
-                sp: 0x200, // end of interpreter space,
             },
-            memory: vec![0; 4096], // spoilt! a whole 4k!
+            memory: vec![0; MEMORY_SIZE], // spoilt! a whole 4k!
+            stack: Stack::default(),
+            memory_mapped_stack: false,
+            display: Display::new(),
+            keypad: Keypad::new(),
+            key_wait: None,
+            font_base: font::DEFAULT_FONT_BASE,
+            big_font_base: font::DEFAULT_BIG_FONT_BASE,
+            flags: Box::new(MemoryFlagStorage::new()),
+            exited: false,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            quirks: Quirks::default(),
+            vblank_wait: false,
             dt: 0,
             st: 0,
-            timedelta_error: 0,
+            buzzed: false,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
             prng_val: 0x0badf00d
-        }
+        };
+
+        // the interpreter area starts with a jump to the program, as if
+        // it had been entered at 0 like on the VIP
+        cpu.write_word(0, 0x1200).expect("memory too small for interpreter area");
+        cpu.load_font(&font::STANDARD, font::DEFAULT_FONT_BASE).expect("memory too small for font");
+        cpu.load_big_font(&font::SCHIP_BIG, font::DEFAULT_BIG_FONT_BASE).expect("memory too small for font");
+        cpu
+    }
+
+    // install `font` at `base`, FX29 will point into it from now on
+    pub fn load_font(&mut self, font: &Font, base: u16) -> Result<(), CpuError> {
+        self.write_bytes(base, font)?;
+        self.font_base = base;
+        Ok(())
+    }
+
+    pub fn get_font_base(&self) -> u16 {
+        self.font_base
+    }
+
+    // same as load_font but for the large font used by FX30
+    pub fn load_big_font(&mut self, font: &BigFont, base: u16) -> Result<(), CpuError> {
+        self.write_bytes(base, font)?;
+        self.big_font_base = base;
+        Ok(())
+    }
+
+    pub fn get_big_font_base(&self) -> u16 {
+        self.big_font_base
+    }
+
+    // the pattern from the last F002, if there's been one
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // where FX75/FX85 keep the RPL flags, in memory unless told otherwise
+    pub fn set_flag_storage(&mut self, storage: Box<dyn FlagStorage>) {
+        self.flags = storage;
+    }
+
+    pub fn flag_storage(&mut self) -> &mut dyn FlagStorage {
+        self.flags.as_mut()
+    }
+
+    // true once the program has run 00FD
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    // copy `rom` into memory at `addr` and start executing from there
+    pub fn load_rom(&mut self, addr: u16, rom: &[u8]) -> Result<(), CpuError> {
+        self.write_bytes(addr, rom)?;
+        self.go(addr);
+        Ok(())
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    // grow (or shrink) memory, keeping whatever fits. XO-CHIP programs get
+    // the full 64k
+    pub fn resize_memory(&mut self, size: usize) {
+        self.memory.resize(size, 0);
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
     }
 
     pub fn go(&mut self, addr: u16) {
@@ -78,105 +227,229 @@ impl CPU {
         self.regs.pc
     }
 
-    pub fn push_word(&mut self, data: u16) {
-        self.regs.sp -= 2;
-        self.write_word(self.regs.sp, data);
+    pub fn get_v(&self, x: u8) -> u8 {
+        self.regs.v_regs[(x & 0xf) as usize]
+    }
+
+    pub fn set_v(&mut self, x: u8, value: u8) {
+        self.regs.v_regs[(x & 0xf) as usize] = value;
+    }
+
+    pub fn get_i(&self) -> u16 {
+        self.regs.i
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        self.regs.i = value;
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.dt
     }
 
-    pub fn pop_word(&mut self) -> u16 {
-        let data =self.read_word(self.regs.sp);
-        self.regs.sp += 2;
-        data
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.dt = value;
     }
 
-    pub fn write_byte(&mut self, addr: u16, data: u8) {
+    pub fn get_sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.st = value;
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.keypad.press(key);
+
+        if let Some(wait) = self.key_wait.as_mut() {
+            if wait.key.is_none() {
+                wait.key = Some(key & 0xf);
+            }
+        }
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.keypad.release(key);
+
+        if let Some(wait) = self.key_wait {
+            if wait.key == Some(key & 0xf) {
+                self.regs.v_regs[wait.reg as usize] = key & 0xf;
+                self.key_wait = None;
+            }
+        }
+    }
+
+    // true while an FX0A is blocking execution
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn set_stack_depth(&mut self, depth: StackDepth) {
+        self.stack.set_depth(depth);
+    }
+
+    pub fn set_memory_mapped_stack(&mut self, enabled: bool) {
+        self.memory_mapped_stack = enabled;
+    }
+
+    // make sure `len` bytes from `addr` are inside memory before touching
+    // any of them, so a failed access never leaves a partial write behind
+    fn check_range(&self, addr: u16, len: usize) -> Result<(), CpuError> {
+        if addr as usize + len > self.memory.len() {
+            return Err(CpuError::MemoryOutOfBounds { addr, len });
+        }
+        Ok(())
+    }
+
+    pub fn write_byte(&mut self, addr: u16, data: u8) -> Result<(), CpuError> {
+        self.check_range(addr, 1)?;
         self.memory[addr as usize] = data;
+        Ok(())
     }
 
-    pub fn write_word(&mut self, addr: u16, data: u16) {
-        let bytes = data.to_be_bytes();
-        self.write_byte(addr, bytes[0]);
-        self.write_byte(addr + 1, bytes[1]);
+    pub fn write_word(&mut self, addr: u16, data: u16) -> Result<(), CpuError> {
+        self.write_bytes(addr, &data.to_be_bytes())
     }
 
-    pub fn read_byte(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    pub fn read_byte(&self, addr: u16) -> Result<u8, CpuError> {
+        self.check_range(addr, 1)?;
+        Ok(self.memory[addr as usize])
     }
 
-    pub fn read_word(&self, addr: u16) -> u16 {
-        let bytes : [u8; 2] = self.memory[addr as usize..addr as usize+2].try_into().expect("unable to read at address");
-        u16::from_be_bytes(bytes)
+    pub fn read_word(&self, addr: u16) -> Result<u16, CpuError> {
+        self.check_range(addr, 2)?;
+        let bytes : [u8; 2] = self.memory[addr as usize..addr as usize+2].try_into().unwrap();
+        Ok(u16::from_be_bytes(bytes))
     }
 
-    pub fn write_bytes(&mut self, addr: u16, data: &Vec<u8>) {
-        self.memory.splice(addr as usize..addr as usize+data.len(), data.iter().cloned());
+    pub fn write_bytes(&mut self, addr: u16, data: &[u8]) -> Result<(), CpuError> {
+        self.check_range(addr, data.len())?;
+        self.memory[addr as usize..addr as usize+data.len()].copy_from_slice(data);
+        Ok(())
     }
 
-    pub fn read_bytes(&mut self, addr: u16, size: usize) -> Vec<u8> {
-        let mut v : Vec<u8> = Vec::new();
-        v.clone_from_slice(&self.memory[addr as usize .. addr as usize + size]);
-        v
+    pub fn read_bytes(&self, addr: u16, size: usize) -> Result<Vec<u8>, CpuError> {
+        self.check_range(addr, size)?;
+        Ok(self.memory[addr as usize .. addr as usize + size].to_vec())
     }
 
     pub fn read_memory(&self) -> Vec<u8> {
         self.memory.clone()
     }
 
-    fn do_ret(&mut self) {
-        self.regs.pc = self.pop_word();
+    fn do_ret(&mut self) -> Result<(), CpuError> {
+        self.regs.pc = self.stack.pop()?;
+        Ok(())
     }
 
-    fn do_call(&mut self, addr: u16) {
-        // TODO: push to stack
-        self.push_word(self.regs.pc + 2);
+    // pc has already been moved past the call, so that's the return address
+    fn do_call(&mut self, addr: u16) -> Result<(), CpuError> {
+        // find the memory slot first so a failed call leaves the stack alone
+        let slot = if self.memory_mapped_stack {
+            let slot = (stack::VIP_STACK_TOP as usize)
+                .checked_sub(2 * (self.stack.len() + 1))
+                .ok_or(CpuError::StackOverflow)? as u16;
+            self.check_range(slot, 2)?;
+            Some(slot)
+        } else {
+            None
+        };
+
+        self.stack.push(self.regs.pc)?;
+        if let Some(slot) = slot {
+            self.write_word(slot, self.regs.pc)?;
+        }
+
         self.regs.pc = addr;
+        Ok(())
     }
 
     fn clear_screen(&mut self) {
+        self.display.clear();
+    }
+
+    // draw an `n` byte sprite from I at (Vx, Vy), or a 16x16 one if `n` is
+    // 0. each selected plane takes its own copy of the sprite data, one after
+    // the other. VF is set on collision
+    fn draw(&mut self, x: u8, y: u8, n: u8) -> Result<(), CpuError> {
+        let x = self.regs.v_regs[x as usize] as usize;
+        let y = self.regs.v_regs[y as usize] as usize;
+        let wrap = !self.quirks.clip_sprites;
+        let planes = self.display.planes().count_ones() as usize;
 
+        let collision = if n == 0 {
+            let sprite = self.read_bytes(self.regs.i, 32 * planes)?;
+            self.display.draw_large_sprite(x, y, &sprite, wrap)
+        } else {
+            let sprite = self.read_bytes(self.regs.i, n as usize * planes)?;
+            self.display.draw_sprite(x, y, &sprite, wrap)
+        };
+        self.set_vf_cond(collision);
+        self.vblank_wait = self.quirks.display_wait;
+        Ok(())
     }
 
-    fn set_vf(&mut self) {
-        self.regs.v_regs[0xf] = 1;
+    fn key_pressed(&self, reg: u8) -> bool {
+        self.keypad.is_pressed(self.regs.v_regs[reg as usize])
     }
 
-    fn set_vf_cond(&mut self, cond: bool) {
-        self.regs.v_regs[0xf] = if cond { 1 } else { 0 }
+    fn wait_for_key(&mut self, reg: u8) {
+        self.key_wait = Some(KeyWait { reg, key: None });
     }
 
-    fn clear_vf(&mut self) {
-        self.regs.v_regs[0xf] = 0;
+    fn set_vf_cond(&mut self, cond: bool) {
+        self.regs.v_regs[0xf] = if cond { 1 } else { 0 }
     }
 
-    fn binary_reg_op(&mut self, dest: u8, src: u8, op: u8) {
+    // 8XY? ALU ops, all arithmetic wraps. the flag is written after the result so
+    // that when Vx is VF the flag is what's left behind
+    fn binary_reg_op(&mut self, dest: u8, src: u8, insn: Instruction) {
         let x = self.regs.v_regs[dest as usize];
         let y = self.regs.v_regs[src as usize];
-        self.regs.v_regs[dest as usize] = match op {
-            // LD
-            0x0 => y,
-            // OR
-            0x1 => x | y,
-            // AND
-            0x2 => x & y,
-            // XOR
-            0x3 => x ^ y,
+        let logic_flag = if self.quirks.logic_resets_vf { Some(0) } else { None };
+        let shift_src = if self.quirks.shift_uses_vy { y } else { x };
+        let (result, flag) = match insn {
+            Instruction::LdReg(..) => (y, None),
+            Instruction::Or(..) => (x | y, logic_flag),
+            Instruction::And(..) => (x & y, logic_flag),
+            Instruction::Xor(..) => (x ^ y, logic_flag),
             // ADD with carry
-            0x4 => { self.set_vf_cond(x as usize + y as usize > 255); x + y },
-            // SUB with borrow
-            0x5 => { self.set_vf_cond(x > y); x - y },
-            // SHR, sets VF to LSB of Vx
-            0x6 => { self.regs.v_regs[0xf] = x & 1; x >> 1 },
+            Instruction::AddReg(..) => { let (r, carry) = x.overflowing_add(y); (r, Some(carry as u8)) },
             // SUB with NOT borrow
-            0x7 => { self.set_vf_cond(y > x); y - x },
-            // SHL, sets VF to MSB of Vx
-            0xe => { self.regs.v_regs[0xf] = x >> 7; x << 1 },
-            _ => 0, // TODO better?
+            Instruction::Sub(..) => { let (r, borrow) = x.overflowing_sub(y); (r, Some(!borrow as u8)) },
+            // SHR, sets VF to the bit shifted out
+            Instruction::Shr(..) => (shift_src >> 1, Some(shift_src & 1)),
+            // SUBN with NOT borrow
+            Instruction::Subn(..) => { let (r, borrow) = y.overflowing_sub(x); (r, Some(!borrow as u8)) },
+            // SHL, sets VF to the bit shifted out
+            Instruction::Shl(..) => (shift_src << 1, Some(shift_src >> 7)),
+            _ => unreachable!("not an ALU instruction: {}", insn),
+        };
+
+        self.regs.v_regs[dest as usize] = result;
+        if let Some(flag) = flag {
+            self.regs.v_regs[0xf] = flag;
         }
     }
 
+    // skips have to step over both words of F000 NNNN
     fn skip_cond(&mut self, cond: bool) {
         if cond {
-            self.regs.pc += 2;
+            let size = if self.read_word(self.regs.pc) == Ok(0xf000) { 4 } else { 2 };
+            self.regs.pc = self.regs.pc.wrapping_add(size);
         }
     }
 
@@ -189,89 +462,646 @@ impl CPU {
         self.prng_val as u8
     }
 
-    // store `nr` (must be <= 8) registers from `addr`
-    fn store_regs(&mut self, addr: u16, nr: u8) {
-        assert!(nr <= 8);
+    // store V0 through Vx (inclusive) from `addr`
+    fn store_regs(&mut self, addr: u16, x: u8) -> Result<(), CpuError> {
+        let len = x as usize + 1;
+        self.check_range(addr, len)?;
 
-        for i in 0..nr {
-            self.write_byte(addr + i as u16, self.regs.v_regs[i as usize]);
+        for i in 0..len {
+            self.memory[addr as usize + i] = self.regs.v_regs[i];
         }
+        self.advance_i(len);
+        Ok(())
+    }
+
+    // load V0 through Vx (inclusive) from `addr`
+    fn load_regs(&mut self, addr: u16, x: u8) -> Result<(), CpuError> {
+        let len = x as usize + 1;
+        self.check_range(addr, len)?;
+
+        for i in 0..len {
+            self.regs.v_regs[i] = self.memory[addr as usize + i];
+        }
+        self.advance_i(len);
+        Ok(())
+    }
+
+    fn advance_i(&mut self, len: usize) {
+        if self.quirks.load_store_increments_i {
+            self.regs.i = self.regs.i.wrapping_add(len as u16);
+        }
+    }
+
+    // the registers from Vx to Vy inclusive, in that order, so backwards if
+    // x > y
+    fn reg_range(x: u8, y: u8) -> Vec<usize> {
+        if x <= y {
+            (x as usize..=y as usize).collect()
+        } else {
+            (y as usize..=x as usize).rev().collect()
+        }
+    }
+
+    // 5XY2, store Vx..Vy from I without changing I
+    fn save_range(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        let data: Vec<u8> = Self::reg_range(x, y).into_iter().map(|r| self.regs.v_regs[r]).collect();
+        self.write_bytes(self.regs.i, &data)
+    }
+
+    // 5XY3, load Vx..Vy from I without changing I
+    fn load_range(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        let regs = Self::reg_range(x, y);
+        let data = self.read_bytes(self.regs.i, regs.len())?;
+        for (reg, val) in regs.into_iter().zip(data) {
+            self.regs.v_regs[reg] = val;
+        }
+        Ok(())
+    }
+
+    // FX75, store V0 through Vx (inclusive) in the RPL flags, leaving the
+    // rest of them alone
+    fn save_flags(&mut self, x: u8) -> Result<(), CpuError> {
+        let len = x as usize + 1;
+        let mut flags = self.flags.load()?;
+        flags[..len].copy_from_slice(&self.regs.v_regs[..len]);
+        self.flags.save(&flags)?;
+        Ok(())
+    }
+
+    // FX85, load V0 through Vx (inclusive) from the RPL flags
+    fn load_flags(&mut self, x: u8) -> Result<(), CpuError> {
+        let len = x as usize + 1;
+        let flags = self.flags.load()?;
+        self.regs.v_regs[..len].copy_from_slice(&flags[..len]);
+        Ok(())
+    }
+
+    // store the hundreds, tens and ones digits of Vx from `addr`
+    fn store_bcd(&mut self, addr: u16, x: u8) -> Result<(), CpuError> {
+        let val = self.regs.v_regs[x as usize];
+        self.write_bytes(addr, &[val / 100, val / 10 % 10, val % 10])
+    }
+
+    pub fn set_instructions_per_frame(&mut self, n: u32) {
+        self.instructions_per_frame = n;
+    }
+
+    pub fn get_instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
     }
 
-    // load `nr` (must be <= 8) registers from `addr`
-    fn load_regs(&mut self, addr: u16, nr: u8) {
-        assert!(nr <= 8);
+    // should be called at TIMER_HZ, run_frame does this for you
+    pub fn tick_timers(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+    }
 
-        for i in 0..nr {
-            self.regs.v_regs[i as usize] = self.read_byte(addr + i as u16);
+    // true while the buzzer should be sounding
+    pub fn sound_active(&self) -> bool {
+        self.st > 0
+    }
+
+    // true if the buzzer sounded through the last frame. sound_active can't
+    // tell you this as the timer has already ticked, a sound timer of 1 is
+    // one frame of sound but is 0 by the time run_frame returns
+    pub fn buzzed_last_frame(&self) -> bool {
+        self.buzzed
+    }
+
+    // run one 1/60th of a second: a frame's worth of instructions followed
+    // by a timer tick
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        for _ in 0..self.instructions_per_frame {
+            self.clock()?;
+            if self.vblank_wait || self.exited {
+                break;
+            }
         }
+        self.end_frame();
+        Ok(())
     }
 
-    pub fn clock(&mut self, ms_delta: u32) {
-        // decrement timers
-        let timer_ticks = (ms_delta + self.timedelta_error) / 16;
-        self.timedelta_error = (ms_delta + self.timedelta_error) % 16;
-        self.st = cmp::min(0, self.st - timer_ticks as u8);
-        self.dt = cmp::min(0, self.dt - timer_ticks as u8);
+    // the 60 Hz tick at the end of a frame, for anything driving clock
+    // itself rather than using run_frame
+    pub fn end_frame(&mut self) {
+        self.vblank_wait = false;
+        self.buzzed = self.st > 0;
+        self.tick_timers();
+    }
 
-        let insn = self.read_word(self.regs.pc);
-        let insn_bytes = insn.to_be_bytes();
-        let insn_nibbles = bytes_to_nibbles(insn_bytes.iter());
-        self.regs.pc += 2;
+    // true once a DXYN under the display wait quirk has ended the frame,
+    // nothing should be clocked until end_frame
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.vblank_wait
+    }
 
-        println!("executing: {:04x} (bytes: {:02x?}, nibbles: {:x?})", insn, insn_bytes, insn_nibbles);
+    // execute a single instruction
+    pub fn clock(&mut self) -> Result<(), CpuError> {
+        // FX0A halts everything but the timers until a key is released
+        if self.key_wait.is_some() || self.exited {
+            return Ok(());
+        }
+
+        let addr = self.regs.pc;
+        let opcode = self.read_word(addr)?;
+        let next = if opcode == 0xf000 { self.read_word(addr.wrapping_add(2))? } else { 0 };
+        let insn = Instruction::decode_long(opcode, next)
+            .map_err(|_| CpuError::InvalidOpcode { addr, opcode })?;
+        self.regs.pc = addr.wrapping_add(insn.size());
+
+        // leave pc pointing at whatever went wrong
+        self.execute(insn).inspect_err(|_| self.regs.pc = addr)
+    }
+
+    fn execute(&mut self, insn: Instruction) -> Result<(), CpuError> {
+        use Instruction::*;
 
         match insn {
-            0x00e0 => self.clear_screen(),
-            0x00ee => self.do_ret(),
-            // JP (absolute jump)
-            0x1000..=0x1fff => self.regs.pc = insn & 0xfff,
-            // CALL (subroutine call)
-            0x2000..=0x2fff => self.do_call(insn & 0xfff),
-            // SE (skip if equal Vx immediate)
-            0x3000..=0x3fff => self.skip_cond(self.regs.v_regs[insn_nibbles[1] as usize] == insn_bytes[1]),
-            // SNE (skip if not equal Vx immediate)
-            0x4000..=0x4fff => self.skip_cond(self.regs.v_regs[insn_nibbles[1] as usize] != insn_bytes[1]),
-            // SE (skip if equal Vx Vy)
-            0x5000..=0x5fff => self.skip_cond(self.regs.v_regs[insn_nibbles[1] as usize] == self.regs.v_regs[insn_nibbles[2] as usize]),
-            0x6000..=0x6fff => self.regs.v_regs[insn_nibbles[1] as usize] = insn_bytes[1],
-            0x7000..=0x7fff => self.regs.v_regs[insn_nibbles[1] as usize] = self.regs.v_regs[insn_nibbles[1] as usize] + insn_bytes[1],
-            0x8000..=0x8fff => self.binary_reg_op(insn_nibbles[1], insn_nibbles[2], insn_nibbles[3]),           
-            // register SNE (skip if not equal Vx Vy)
-            0x9000..=0x9fff => self.skip_cond(self.regs.v_regs[insn_nibbles[1] as usize] != self.regs.v_regs[insn_nibbles[2] as usize]),
-            // set I to immediate value
-            0xa000..=0xafff => self.regs.i = nibbles3_to_u16(insn_nibbles),
-            // jump to immediate offset by V0
-            0xb000..=0xbfff => self.regs.pc = nibbles3_to_u16(insn_nibbles) + self.regs.v_regs[0] as u16,
+            ScrollDown(n) => self.display.scroll_down(n as usize),
+            Cls => self.clear_screen(),
+            ScrollRight => self.display.scroll_right(4),
+            ScrollLeft => self.display.scroll_left(4),
+            Exit => self.exited = true,
+            Lores => self.display.set_hires(false),
+            Hires => self.display.set_hires(true),
+            Ret => self.do_ret()?,
+            ScrollUp(n) => self.display.scroll_up(n as usize),
+            // we can't run machine code routines
+            Sys(_) => return Err(CpuError::InvalidOpcode {
+                addr: self.regs.pc.wrapping_sub(2),
+                opcode: insn.encode(),
+            }),
+            Jp(addr) => self.regs.pc = addr,
+            Call(addr) => self.do_call(addr)?,
+            Se(x, nn) => self.skip_cond(self.regs.v_regs[x as usize] == nn),
+            Sne(x, nn) => self.skip_cond(self.regs.v_regs[x as usize] != nn),
+            SeReg(x, y) => self.skip_cond(self.regs.v_regs[x as usize] == self.regs.v_regs[y as usize]),
+            SaveRange(x, y) => self.save_range(x, y)?,
+            LoadRange(x, y) => self.load_range(x, y)?,
+            Ld(x, nn) => self.regs.v_regs[x as usize] = nn,
+            Add(x, nn) => {
+                let vx = &mut self.regs.v_regs[x as usize];
+                *vx = vx.wrapping_add(nn);
+            },
+            LdReg(x, y) | Or(x, y) | And(x, y) | Xor(x, y) | AddReg(x, y)
+            | Sub(x, y) | Shr(x, y) | Subn(x, y) | Shl(x, y) => self.binary_reg_op(x, y, insn),
+            SneReg(x, y) => self.skip_cond(self.regs.v_regs[x as usize] != self.regs.v_regs[y as usize]),
+            LdI(addr) => self.regs.i = addr,
+            JpV0(addr) => {
+                let reg = if self.quirks.jump_uses_vx { (addr >> 8) & 0xf } else { 0 };
+                self.regs.pc = addr + self.regs.v_regs[reg as usize] as u16;
+            },
             // generate a random byte and store its AND with immediate value in Vx
-            0xc000..=0xcfff => self.regs.v_regs[insn_nibbles[1] as usize] = self.random() & insn_bytes[1],
-            // Draw N-byte sprite
-            // 0xd000..=0xdfff => ,
-            // Skip if key Vx is pressed
-            // 0xe09e..=0xef9e => ,
-            // Skip if key Vx is not pressed
-            // 0xe0a1..=0xefa1 => ,
-            // Load Vx with the delay timer value
-            0xf007..=0xff07 => self.regs.v_regs[insn_nibbles[1] as usize] = self.dt,
-            // Wait for a keypress and store the key in Vx
-            // 0xf00a..=0xff0a => ,
-            // Set delay timer value to Vx
-            0xf015..=0xff15 => self.dt = self.regs.v_regs[insn_nibbles[1] as usize],
-            // Set sound timer to Vx
-            0xf018..=0xff18 => self.st = self.regs.v_regs[insn_nibbles[1] as usize],
-            // set I = I + vx
-            0xf01e..=0xff1e => self.regs.i = self.regs.v_regs[insn_nibbles[1] as usize] as u16 + self.regs.i,
-            // set I to the location of sprite for digit Vx
-            // 0xf029..=0xff29 => ,
-            // store the BCD representation of Vx in I..I+2
-            // 0xf033..=0xff33 => 
-            // store V0 through Vx from I..I+x
-            0xf055..=0xff55 => self.store_regs(self.regs.i, insn_nibbles[1]),
-            // load V0 through Vx from I..I+x
-            0xf065..=0xff65 => self.load_regs(self.regs.i, insn_nibbles[1]),
-
-            // Undefined opcode
-            _ => panic!("undefined opcode"),
+            Rnd(x, nn) => self.regs.v_regs[x as usize] = self.random() & nn,
+            Drw(x, y, n) => self.draw(x, y, n)?,
+            Skp(x) => self.skip_cond(self.key_pressed(x)),
+            Sknp(x) => self.skip_cond(!self.key_pressed(x)),
+            LdILong(addr) => self.regs.i = addr,
+            Plane(n) => self.display.select_planes(n),
+            Audio => self.audio_pattern = Some(self.read_bytes(self.regs.i, 16)?.try_into().unwrap()),
+            GetDelay(x) => self.regs.v_regs[x as usize] = self.dt,
+            WaitKey(x) => self.wait_for_key(x),
+            SetDelay(x) => self.dt = self.regs.v_regs[x as usize],
+            SetSound(x) => self.st = self.regs.v_regs[x as usize],
+            AddI(x) => self.regs.i = self.regs.i.wrapping_add(self.regs.v_regs[x as usize] as u16),
+            // point I at the glyph for the low nibble of Vx
+            LdFont(x) => {
+                let digit = (self.regs.v_regs[x as usize] & 0xf) as u16;
+                self.regs.i = self.font_base.wrapping_add(digit * font::GLYPH_SIZE as u16);
+            },
+            LdBigFont(x) => {
+                let digit = (self.regs.v_regs[x as usize] & 0xf) as u16;
+                self.regs.i = self.big_font_base.wrapping_add(digit * font::BIG_GLYPH_SIZE as u16);
+            },
+            Bcd(x) => self.store_bcd(self.regs.i, x)?,
+            Pitch(x) => self.pitch = self.regs.v_regs[x as usize],
+            StoreRegs(x) => self.store_regs(self.regs.i, x)?,
+            LoadRegs(x) => self.load_regs(self.regs.i, x)?,
+            SaveFlags(x) => self.save_flags(x)?,
+            LoadFlags(x) => self.load_flags(x)?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::QuirkProfile;
+
+    // a CPU with `program` loaded at 0x200 under `profile`
+    fn load(profile: QuirkProfile, program: &[u16]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.set_quirks(profile.quirks());
+        cpu.set_stack_depth(profile.stack_depth());
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        cpu.load_rom(PROGRAM_START, &rom).unwrap();
+        cpu
+    }
+
+    fn run(cpu: &mut CPU, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.clock().unwrap();
+        }
+    }
+
+    #[test]
+    fn bcd() {
+        let mut cpu = load(QuirkProfile::Vip, &[0xa300, 0xf533]);
+        cpu.set_v(5, 254);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.read_bytes(0x300, 3), Ok(vec![2, 5, 4]));
+        assert_eq!(cpu.get_i(), 0x300);
+
+        cpu.set_i(0xffe);
+        cpu.go(0x202);
+        assert_eq!(cpu.clock(), Err(CpuError::MemoryOutOfBounds { addr: 0xffe, len: 3 }));
+        assert_eq!(cpu.read_bytes(0xffe, 2), Ok(vec![0, 0]));
+    }
+
+    #[test]
+    fn store_and_load_every_register() {
+        for (profile, i) in [(QuirkProfile::Vip, 0x310), (QuirkProfile::Schip, 0x300)] {
+            let mut cpu = load(profile, &[0xa300, 0xff55, 0xa300, 0x6f00, 0xff65]);
+            for x in 0..16 {
+                cpu.set_v(x, x * 3 + 1);
+            }
+            run(&mut cpu, 2);
+            assert_eq!(cpu.read_bytes(0x300, 17).unwrap(), (0..16).map(|x| x * 3 + 1).chain([0]).collect::<Vec<u8>>());
+            assert_eq!(cpu.get_i(), i, "{}", profile);
+
+            run(&mut cpu, 3);
+            assert_eq!(cpu.get_v(0xf), 46, "{}", profile);
+            assert_eq!(cpu.get_i(), i, "{}", profile);
+        }
+    }
+
+    #[test]
+    fn errors_leave_pc_at_the_instruction() {
+        let mut cpu = load(QuirkProfile::Vip, &[0x00ee]);
+        assert_eq!(cpu.clock(), Err(CpuError::StackUnderflow));
+        assert_eq!(cpu.get_pc(), 0x200);
+
+        for opcode in [0x5121, 0x8008, 0xe0a0, 0x0123] {
+            let mut cpu = load(QuirkProfile::Vip, &[0x6001, opcode]);
+            run(&mut cpu, 1);
+            assert_eq!(cpu.clock(), Err(CpuError::InvalidOpcode { addr: 0x202, opcode }));
+            assert_eq!(cpu.get_pc(), 0x202);
+        }
+
+        let mut cpu = load(QuirkProfile::Vip, &[]);
+        cpu.go(0xfff);
+        assert_eq!(cpu.clock(), Err(CpuError::MemoryOutOfBounds { addr: 0xfff, len: 2 }));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut cpu = load(QuirkProfile::Vip, &[0x2200]);
+        run(&mut cpu, 12);
+        assert_eq!(cpu.clock(), Err(CpuError::StackOverflow));
+        assert_eq!(cpu.stack().len(), 12);
+        assert_eq!(cpu.get_pc(), 0x200);
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(CpuError::InvalidOpcode { addr: 0x202, opcode: 0x5121 }.to_string(), "invalid opcode 5121 at 0x202");
+        assert_eq!(CpuError::StackUnderflow.to_string(), "stack underflow");
+        assert_eq!(
+            CpuError::MemoryOutOfBounds { addr: 0xffe, len: 3 }.to_string(),
+            "memory access out of bounds: 0xffe..0x1001",
+        );
+    }
+
+    // 8F1N with VF and V1 set to `vf` and `v1` first, giving VF after
+    fn alu_into_vf(profile: QuirkProfile, opcode: u16, vf: u8, v1: u8) -> u8 {
+        let mut cpu = load(profile, &[opcode]);
+        cpu.set_v(0xf, vf);
+        cpu.set_v(1, v1);
+        run(&mut cpu, 1);
+        cpu.get_v(0xf)
+    }
+
+    #[test]
+    fn flag_wins_when_vx_is_vf() {
+        for profile in [QuirkProfile::Vip, QuirkProfile::Schip, QuirkProfile::XoChip] {
+            // 0xff + 3 is 2 with a carry
+            assert_eq!(alu_into_vf(profile, 0x8f14, 0xff, 0x03), 1, "{}", profile);
+            // 1 - 3 borrows, 5 - 3 doesn't
+            assert_eq!(alu_into_vf(profile, 0x8f15, 0x01, 0x03), 0, "{}", profile);
+            assert_eq!(alu_into_vf(profile, 0x8f15, 0x05, 0x03), 1, "{}", profile);
+            // 3 - 5 borrows
+            assert_eq!(alu_into_vf(profile, 0x8f17, 0x05, 0x03), 0, "{}", profile);
+        }
+
+        // the shift is of VY on the VIP and XO-CHIP, of VX on SCHIP
+        assert_eq!(alu_into_vf(QuirkProfile::Vip, 0x8f16, 0x05, 0x06), 0);
+        assert_eq!(alu_into_vf(QuirkProfile::XoChip, 0x8f16, 0x04, 0x07), 1);
+        assert_eq!(alu_into_vf(QuirkProfile::Schip, 0x8f16, 0x05, 0x06), 1);
+        assert_eq!(alu_into_vf(QuirkProfile::Vip, 0x8f1e, 0x80, 0x40), 0);
+        assert_eq!(alu_into_vf(QuirkProfile::Schip, 0x8f1e, 0x80, 0x40), 1);
+    }
+
+    #[test]
+    fn arithmetic_wraps() {
+        let mut cpu = load(QuirkProfile::Vip, &[0x70ff, 0x8124, 0x8325, 0x8427, 0x851e]);
+        let regs = [(0, 0x02), (1, 0xff), (2, 0x01), (3, 0x00), (4, 0x03), (5, 0x00), (0xf, 0x42)];
+        regs.iter().for_each(|&(x, value)| cpu.set_v(x, value));
+
+        // 7XNN doesn't touch VF
+        run(&mut cpu, 1);
+        assert_eq!((cpu.get_v(0), cpu.get_v(0xf)), (0x01, 0x42));
+        run(&mut cpu, 1);
+        assert_eq!((cpu.get_v(1), cpu.get_v(0xf)), (0x00, 1));
+        run(&mut cpu, 1);
+        assert_eq!((cpu.get_v(3), cpu.get_v(0xf)), (0xff, 0));
+        // 0x01 - 0x03, with V2 as VY
+        cpu.set_v(2, 0x01);
+        cpu.set_v(4, 0x03);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.get_v(4), cpu.get_v(0xf)), (0xfe, 0));
+        // the top bit shifts out of VY into VF
+        cpu.set_v(1, 0x81);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.get_v(5), cpu.get_v(0xf)), (0x02, 1));
+    }
+
+    #[test]
+    fn logic_quirk() {
+        for (profile, vf) in [(QuirkProfile::Vip, 0), (QuirkProfile::Chip48, 0x42), (QuirkProfile::XoChip, 0x42)] {
+            let mut cpu = load(profile, &[0x8011, 0x8012, 0x8013]);
+            for _ in 0..3 {
+                cpu.set_v(0xf, 0x42);
+                run(&mut cpu, 1);
+                assert_eq!(cpu.get_v(0xf), vf, "{}", profile);
+            }
+        }
+    }
+
+    #[test]
+    fn jump_quirk() {
+        for (profile, pc) in [(QuirkProfile::Vip, 0x311), (QuirkProfile::Schip, 0x322)] {
+            let mut cpu = load(profile, &[0xb310]);
+            cpu.set_v(0, 0x01);
+            cpu.set_v(3, 0x12);
+            run(&mut cpu, 1);
+            assert_eq!(cpu.get_pc(), pc, "{}", profile);
+        }
+    }
+
+    #[test]
+    fn sprite_quirks() {
+        for (profile, wrapped, frame_ended) in [
+            (QuirkProfile::Vip, false, true),
+            (QuirkProfile::Schip, false, false),
+            (QuirkProfile::XoChip, true, false),
+        ] {
+            // a row of 8 pixels, 4 from the right hand edge
+            let mut cpu = load(profile, &[0xa300, 0xd011]);
+            cpu.write_byte(0x300, 0xff).unwrap();
+            cpu.set_v(0, 60);
+            run(&mut cpu, 2);
+
+            assert!(cpu.display().get(63, 0), "{}", profile);
+            assert_eq!(cpu.display().get(0, 0), wrapped, "{}", profile);
+            assert_eq!(cpu.is_waiting_for_vblank(), frame_ended, "{}", profile);
+        }
+    }
+
+    #[test]
+    fn draw_and_clear() {
+        let mut cpu = load(QuirkProfile::XoChip, &[0xa300, 0xd011, 0xd011, 0x00e0]);
+        cpu.write_byte(0x300, 0x80).unwrap();
+        cpu.set_v(0, 5);
+        cpu.set_v(1, 7);
+
+        run(&mut cpu, 2);
+        assert!(cpu.display().get(5, 7));
+        assert_eq!(cpu.get_v(0xf), 0);
+        run(&mut cpu, 1);
+        assert!(!cpu.display().get(5, 7));
+        assert_eq!(cpu.get_v(0xf), 1);
+
+        cpu.go(0x202);
+        run(&mut cpu, 1);
+        assert!(cpu.display().get(5, 7));
+        cpu.go(0x206);
+        run(&mut cpu, 1);
+        assert!(!cpu.display().get(5, 7));
+    }
+
+    #[test]
+    fn key_skips() {
+        for (pressed, skip_if_pressed, skip_if_not) in [(false, 0x204, 0x206), (true, 0x206, 0x204)] {
+            let mut cpu = load(QuirkProfile::Vip, &[0x6a0b, 0xea9e]);
+            if pressed {
+                cpu.press_key(0xb);
+            }
+            run(&mut cpu, 2);
+            assert_eq!(cpu.get_pc(), skip_if_pressed);
+
+            cpu.go(0x202);
+            cpu.write_word(0x202, 0xeaa1).unwrap();
+            run(&mut cpu, 1);
+            assert_eq!(cpu.get_pc(), skip_if_not);
+        }
+    }
+
+    #[test]
+    fn wait_for_key_press_and_release() {
+        let mut cpu = load(QuirkProfile::Vip, &[0xf30a, 0x6001]);
+        run(&mut cpu, 3);
+        assert!(cpu.is_waiting_for_key());
+        assert_eq!(cpu.get_pc(), 0x202);
+
+        // it's the first key pressed that counts, once it's let go
+        cpu.press_key(0x7);
+        cpu.press_key(0x2);
+        cpu.release_key(0x2);
+        run(&mut cpu, 1);
+        assert!(cpu.is_waiting_for_key());
+
+        cpu.set_delay_timer(3);
+        cpu.end_frame();
+        assert_eq!(cpu.get_delay_timer(), 2);
+
+        cpu.release_key(0x7);
+        assert!(!cpu.is_waiting_for_key());
+        assert_eq!(cpu.get_v(3), 0x7);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_v(0), 1);
+    }
+
+    #[test]
+    fn font() {
+        let mut cpu = load(QuirkProfile::Vip, &[0x601a, 0xf029, 0xf030]);
+        assert_eq!(cpu.read_bytes(font::DEFAULT_FONT_BASE, font::FONT_SIZE), Ok(font::STANDARD.to_vec()));
+
+        // only the low nibble picks the digit
+        run(&mut cpu, 2);
+        assert_eq!(cpu.get_i(), font::DEFAULT_FONT_BASE + 10 * 5);
+        assert_eq!(cpu.read_bytes(cpu.get_i(), 5), Ok(vec![0xf0, 0x90, 0xf0, 0x90, 0x90]));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_i(), font::DEFAULT_BIG_FONT_BASE + 10 * 10);
+
+        cpu.load_font(font::FontSet::Vip.glyphs(), 0x50).unwrap();
+        cpu.go(0x202);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_i(), 0x50 + 10 * 5);
+        assert_eq!(cpu.read_bytes(cpu.get_i(), 5), Ok(font::VIP[50..55].to_vec()));
+    }
+
+    #[test]
+    fn timers_tick_once_a_frame() {
+        // v0 counts instructions
+        let mut cpu = load(QuirkProfile::Schip, &[0x7001, 0x1200]);
+        cpu.set_delay_timer(2);
+        cpu.set_sound_timer(1);
+        cpu.set_instructions_per_frame(20);
+
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.get_v(0), 10);
+        assert_eq!((cpu.get_delay_timer(), cpu.get_sound_timer()), (1, 0));
+        assert!(cpu.buzzed_last_frame());
+        assert!(!cpu.sound_active());
+
+        // and stop at 0 rather than wrapping
+        cpu.run_frame().unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.get_v(0), 30);
+        assert_eq!((cpu.get_delay_timer(), cpu.get_sound_timer()), (0, 0));
+        assert!(!cpu.buzzed_last_frame());
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame() {
+        let mut cpu = load(QuirkProfile::Vip, &[0x7001, 0xd000, 0x1200]);
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.get_pc(), 0x204);
+        assert!(!cpu.is_waiting_for_vblank());
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.get_v(0), 2);
+    }
+
+    #[test]
+    fn call_and_return() {
+        let mut cpu = load(QuirkProfile::Vip, &[0x2206, 0x6001, 0x1204, 0x00ee]);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.get_pc(), cpu.stack().entries()), (0x206, &[0x202][..]));
+        // nothing in memory unless it's asked for
+        assert_eq!(cpu.read_word(stack::VIP_STACK_TOP - 2), Ok(0));
+        run(&mut cpu, 2);
+        assert_eq!((cpu.get_pc(), cpu.get_v(0)), (0x204, 1));
+        assert!(cpu.stack().is_empty());
+    }
+
+    #[test]
+    fn memory_mapped_stack() {
+        let mut cpu = load(QuirkProfile::Vip, &[0x2202, 0x2204]);
+        cpu.set_memory_mapped_stack(true);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.read_bytes(stack::VIP_STACK_TOP - 4, 4), Ok(vec![0x02, 0x04, 0x02, 0x02]));
+
+        // somewhere with no room for it is an error, leaving the stack alone
+        let mut cpu = load(QuirkProfile::Vip, &[0x2200]);
+        cpu.set_memory_mapped_stack(true);
+        cpu.resize_memory(stack::VIP_STACK_TOP as usize - 1);
+        assert_eq!(cpu.clock(), Err(CpuError::MemoryOutOfBounds { addr: stack::VIP_STACK_TOP - 2, len: 2 }));
+        assert!(cpu.stack().is_empty());
+        assert_eq!(cpu.get_pc(), 0x200);
+    }
+
+    #[test]
+    fn schip_instructions() {
+        let mut cpu = load(QuirkProfile::Schip, &[0x00ff, 0x6005, 0xf030, 0xf575, 0x6000, 0xf085, 0x00fe, 0x00fd, 0x6001]);
+        run(&mut cpu, 1);
+        assert!(cpu.display().is_hires());
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.get_i(), font::DEFAULT_BIG_FONT_BASE + 5 * 10);
+
+        // the flags keep V0..V5 while V0 changes underneath
+        run(&mut cpu, 3);
+        assert_eq!(cpu.get_v(0), 5);
+        assert_eq!(cpu.flag_storage().load().unwrap()[..6], [5, 0, 0, 0, 0, 0]);
+
+        run(&mut cpu, 1);
+        assert!(!cpu.display().is_hires());
+        run(&mut cpu, 2);
+        assert!(cpu.has_exited());
+        assert_eq!((cpu.get_pc(), cpu.get_v(0)), (0x210, 5));
+    }
+
+    #[test]
+    fn xochip_instructions() {
+        let program = [0xf000, 0xe000, 0x5132, 0xa300, 0x5313, 0x3000, 0xf000, 0x1234, 0x6007];
+        let mut cpu = load(QuirkProfile::XoChip, &program);
+        cpu.resize_memory(QuirkProfile::XoChip.memory_size());
+        (1..4).for_each(|x| cpu.set_v(x, x * 0x11));
+        cpu.write_bytes(0x300, &[0xaa, 0xbb, 0xcc]).unwrap();
+
+        // a long load reaches the top 60K
+        run(&mut cpu, 2);
+        assert_eq!(cpu.get_i(), 0xe000);
+        assert_eq!(cpu.read_bytes(0xe000, 3), Ok(vec![0x11, 0x22, 0x33]));
+
+        // backwards, and without moving I
+        run(&mut cpu, 2);
+        assert_eq!((cpu.get_i(), cpu.get_v(3), cpu.get_v(2), cpu.get_v(1)), (0x300, 0xaa, 0xbb, 0xcc));
+
+        // skips go over the whole of a long load
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_pc(), 0x210);
+    }
+
+    #[test]
+    fn bitplanes() {
+        let mut cpu = load(QuirkProfile::XoChip, &[0xf201, 0xa300, 0xd001, 0xf301, 0xd001, 0x00e0]);
+        cpu.write_bytes(0x300, &[0x80, 0x40]).unwrap();
+        run(&mut cpu, 3);
+        assert_eq!(cpu.display().pixel(0, 0), 0b10);
+
+        // with both selected each takes its own row of sprite data, and
+        // the first plane had nothing to collide with
+        run(&mut cpu, 2);
+        assert_eq!((cpu.display().pixel(0, 0), cpu.display().pixel(1, 0)), (0b11, 0b10));
+        assert_eq!(cpu.get_v(0xf), 0);
+
+        run(&mut cpu, 1);
+        assert!(cpu.display().pixels().iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn flags_outlive_the_cpu() {
+        use crate::flags::Flags;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // storage that's still there after the CPU has gone
+        #[derive(Debug, Clone, Default)]
+        struct Shared(Rc<RefCell<Flags>>);
+
+        impl FlagStorage for Shared {
+            fn load(&mut self) -> io::Result<Flags> {
+                Ok(*self.0.borrow())
+            }
+
+            fn save(&mut self, flags: &Flags) -> io::Result<()> {
+                *self.0.borrow_mut() = *flags;
+                Ok(())
+            }
         }
+
+        let shared = Shared::default();
+        let mut cpu = load(QuirkProfile::XoChip, &[0xff75]);
+        cpu.set_flag_storage(Box::new(shared.clone()));
+        (0..16).for_each(|x| cpu.set_v(x, 0xf0 | x));
+        run(&mut cpu, 1);
+
+        let mut cpu = load(QuirkProfile::XoChip, &[0xf285]);
+        cpu.set_flag_storage(Box::new(shared));
+        run(&mut cpu, 1);
+        assert_eq!((cpu.get_v(0), cpu.get_v(2), cpu.get_v(3)), (0xf0, 0xf2, 0));
     }
 }
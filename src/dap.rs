@@ -0,0 +1,783 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::cpu::{self, CPU};
+use crate::debugger::{Debugger, Stop};
+use crate::instruction::Instruction;
+use crate::quirks::QuirkProfile;
+use crate::symbols::{self, SymbolMap};
+
+// DAP only knows about threads, and we've only got the one
+const THREAD_ID: i64 = 1;
+
+// variablesReference for each scope
+const REGISTERS_REF: i64 = 1;
+const TIMERS_REF: i64 = 2;
+const STACK_REF: i64 = 3;
+
+// read one Content-Length framed message, None at the end of the input
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::other)
+}
+
+pub fn write_message(out: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+// read messages on their own thread so that requests (pause especially)
+// still arrive while the program is running
+pub fn spawn_reader(mut input: impl BufRead + Send + 'static) -> Receiver<Value> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// decimal, or hex with a 0x prefix, as used by memory and instruction
+// references
+fn parse_reference(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn reference(addr: u16) -> String {
+    format!("{:#06x}", addr)
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// what a running program is heading for
+#[derive(Debug, Clone, Copy)]
+enum Goal {
+    // just keep going until a breakpoint
+    Breakpoint,
+    // until the stack is down to `depth`, and at `pc` if given. for stepping
+    // over calls and out of subroutines
+    Return { pc: Option<u16>, depth: usize },
+}
+
+// a line the client wants to stop at, which may not have any code until a
+// program with symbols has been launched
+#[derive(Debug, Clone, Copy)]
+struct SourceBreakpoint {
+    id: i64,
+    line: u32,
+}
+
+// a Debug Adapter Protocol server, normally talking over stdin and stdout
+pub struct DapServer<W: Write> {
+    out: W,
+    seq: i64,
+    rx: Receiver<Value>,
+    // a message that arrived while running, to handle next
+    pending: Option<Value>,
+    closed: bool,
+    debugger: Option<Debugger>,
+    symbols: SymbolMap,
+    // the debugger has one set of breakpoints, these are merged into it
+    // whenever either changes. source breakpoints are kept by the client's
+    // path and looked up in the symbols each time
+    source_breakpoints: HashMap<String, Vec<SourceBreakpoint>>,
+    next_breakpoint_id: i64,
+    instruction_breakpoints: Vec<u16>,
+    running: Option<Goal>,
+    // execution starts once we've been both configured and launched
+    configured: bool,
+    launched: bool,
+    stop_on_entry: bool,
+    // events to send once the current response has gone out
+    events: Vec<(String, Value)>,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(out: W, rx: Receiver<Value>) -> Self {
+        DapServer {
+            out,
+            seq: 1,
+            rx,
+            pending: None,
+            closed: false,
+            debugger: None,
+            symbols: SymbolMap::new(),
+            source_breakpoints: HashMap::new(),
+            next_breakpoint_id: 1,
+            instruction_breakpoints: Vec::new(),
+            running: None,
+            configured: false,
+            launched: false,
+            stop_on_entry: false,
+            events: Vec::new(),
+        }
+    }
+
+    // a program to attach to, for an attach request rather than launch
+    pub fn set_target(&mut self, debugger: Debugger, symbols: SymbolMap) {
+        self.debugger = Some(debugger);
+        self.symbols = symbols;
+    }
+
+    // serve requests until the client disconnects
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            let message = match self.pending.take() {
+                Some(message) => message,
+                None if self.closed => return Ok(()),
+                None if self.running.is_some() => {
+                    self.run_until_message()?;
+                    continue;
+                },
+                None => match self.rx.recv() {
+                    Ok(message) => message,
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            if !self.dispatch(&message)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        write_message(&mut self.out, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn queue_event(&mut self, event: &str, body: Value) {
+        self.events.push((event.to_string(), body));
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) {
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.queue_event("stopped", body);
+    }
+
+    fn exited(&mut self) {
+        self.queue_event("exited", json!({ "exitCode": 0 }));
+        self.queue_event("terminated", json!({}));
+    }
+
+    // returns false once we should stop serving
+    fn dispatch(&mut self, message: &Value) -> io::Result<bool> {
+        if message["type"] != "request" {
+            return Ok(true);
+        }
+        let command = message["command"].as_str().unwrap_or("");
+        let args = &message["arguments"];
+
+        let result = self.request(command, args);
+        let mut response = json!({
+            "type": "response",
+            "request_seq": message["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(e) => response["message"] = json!(e),
+        }
+        self.send(response)?;
+
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.event(&event, body)?;
+        }
+        Ok(command != "disconnect")
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger, String> {
+        self.debugger.as_mut().ok_or_else(|| "no program has been launched".to_string())
+    }
+
+    fn request(&mut self, command: &str, args: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => {
+                self.queue_event("initialized", json!({}));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsSetVariable": true,
+                }))
+            },
+            "launch" => {
+                let (debugger, symbols) = launch(args)?;
+                self.set_target(debugger, symbols);
+                self.rebind_breakpoints();
+                self.start(args)
+            },
+            "attach" => {
+                self.debugger()?;
+                self.start(args)
+            },
+            "configurationDone" => {
+                self.configured = true;
+                self.begin();
+                Ok(json!({}))
+            },
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS_REF, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REF, "expensive": false },
+            ] })),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "readMemory" => self.read_memory(args),
+            "continue" => {
+                self.debugger()?;
+                self.running = Some(Goal::Breakpoint);
+                Ok(json!({ "allThreadsContinued": true }))
+            },
+            "next" => self.next(),
+            "stepIn" => {
+                let stop = self.debugger()?.step();
+                self.report(stop);
+                Ok(json!({}))
+            },
+            "stepOut" => self.step_out(),
+            "pause" => {
+                if self.running.take().is_some() {
+                    self.stopped("pause", None);
+                }
+                Ok(json!({}))
+            },
+            "disconnect" => Ok(json!({})),
+            _ => Err(format!("unsupported request '{}'", command)),
+        }
+    }
+
+    fn start(&mut self, args: &Value) -> Result<Value, String> {
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.launched = true;
+        self.begin();
+        Ok(json!({}))
+    }
+
+    fn begin(&mut self) {
+        if !self.configured || !self.launched || self.debugger.is_none() {
+            return;
+        }
+        if self.stop_on_entry {
+            self.stopped("entry", None);
+        } else {
+            self.running = Some(Goal::Breakpoint);
+        }
+    }
+
+    // tell the client why we stopped
+    fn report(&mut self, stop: Result<Stop, cpu::CpuError>) {
+        match stop {
+            Ok(Stop::Step) => self.stopped("step", None),
+            Ok(Stop::Breakpoint) => self.stopped("breakpoint", None),
+            Ok(Stop::Interrupted) => self.stopped("pause", None),
+            Ok(Stop::Exited) => self.exited(),
+            Err(e) => self.stopped("exception", Some(e.to_string())),
+        }
+    }
+
+    // run until something happens or a message arrives, which is left in
+    // `pending` while we carry on running
+    fn run_until_message(&mut self) -> io::Result<()> {
+        let goal = match self.running {
+            Some(goal) => goal,
+            None => return Ok(()),
+        };
+        let DapServer { debugger, rx, pending, closed, .. } = self;
+        let debugger = match debugger {
+            Some(debugger) => debugger,
+            None => return Ok(()),
+        };
+
+        let interrupted = || match rx.try_recv() {
+            Ok(message) => {
+                *pending = Some(message);
+                true
+            },
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => {
+                *closed = true;
+                true
+            },
+        };
+        let stop = match goal {
+            Goal::Breakpoint => debugger.cont(interrupted),
+            Goal::Return { pc, depth } => debugger.run_until(interrupted, |cpu| {
+                cpu.stack().len() <= depth && pc.is_none_or(|pc| cpu.get_pc() == pc)
+            }),
+        };
+
+        if stop != Ok(Stop::Interrupted) {
+            self.running = None;
+            self.report(stop);
+            for (event, body) in std::mem::take(&mut self.events) {
+                self.event(&event, body)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Value, String> {
+        let debugger = self.debugger()?;
+        match debugger.current_instruction() {
+            Some(Instruction::Call(_)) => {
+                let cpu = debugger.cpu();
+                self.running = Some(Goal::Return { pc: Some(cpu.get_pc().wrapping_add(2)), depth: cpu.stack().len() });
+            },
+            _ => {
+                let stop = debugger.step();
+                self.report(stop);
+            },
+        }
+        Ok(json!({}))
+    }
+
+    fn step_out(&mut self) -> Result<Value, String> {
+        let debugger = self.debugger()?;
+        match debugger.cpu().stack().len() {
+            // not in a subroutine, so there's nothing to step out of
+            0 => {
+                let stop = debugger.step();
+                self.report(stop);
+            },
+            depth => self.running = Some(Goal::Return { pc: None, depth: depth - 1 }),
+        }
+        Ok(json!({}))
+    }
+
+    // where `breakpoint` in `path` ends up, and what to tell the client
+    // about it
+    fn resolve(&self, path: &str, breakpoint: SourceBreakpoint) -> (Option<u16>, Value) {
+        // the symbols may spell the path differently
+        let entry = self.symbols.lines()
+            .iter()
+            .find(|entry| same_file(&entry.file, path))
+            .and_then(|entry| self.symbols.address_for_line(&entry.file, breakpoint.line));
+
+        match entry {
+            Some(entry) => (Some(entry.addr), json!({
+                "id": breakpoint.id,
+                "verified": true,
+                "line": entry.line,
+                "instructionReference": reference(entry.addr),
+            })),
+            None => (None, json!({
+                "id": breakpoint.id,
+                "verified": false,
+                "line": breakpoint.line,
+                "message": "no code at this line",
+            })),
+        }
+    }
+
+    fn source_breakpoints(&self) -> impl Iterator<Item = (&str, SourceBreakpoint)> + '_ {
+        self.source_breakpoints.iter()
+            .flat_map(|(path, breakpoints)| breakpoints.iter().map(move |&breakpoint| (path.as_str(), breakpoint)))
+    }
+
+    fn update_breakpoints(&mut self) {
+        let addrs: Vec<u16> = self.source_breakpoints()
+            .filter_map(|(path, breakpoint)| self.resolve(path, breakpoint).0)
+            .chain(self.instruction_breakpoints.iter().copied())
+            .collect();
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.clear_breakpoints();
+            for addr in addrs {
+                debugger.add_breakpoint(addr);
+            }
+        }
+    }
+
+    // breakpoints set before launch were looked up without any symbols, so
+    // look them up again and tell the client where they are now
+    fn rebind_breakpoints(&mut self) {
+        let changed: Vec<Value> = self.source_breakpoints()
+            .map(|(path, breakpoint)| self.resolve(path, breakpoint).1)
+            .collect();
+        for breakpoint in changed {
+            self.queue_event("breakpoint", json!({ "reason": "changed", "breakpoint": breakpoint }));
+        }
+        self.update_breakpoints();
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"].as_str().ok_or("setBreakpoints needs a source path")?;
+
+        let mut requested = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as u32;
+            requested.push(SourceBreakpoint { id: self.next_breakpoint_id, line });
+            self.next_breakpoint_id += 1;
+        }
+
+        let breakpoints: Vec<Value> = requested.iter().map(|&breakpoint| self.resolve(path, breakpoint).1).collect();
+        self.source_breakpoints.insert(path.to_string(), requested);
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut breakpoints = Vec::new();
+        self.instruction_breakpoints.clear();
+
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let base = breakpoint["instructionReference"].as_str().and_then(parse_reference);
+            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+            let addr = base.and_then(|base| u16::try_from(base as i64 + offset).ok());
+
+            breakpoints.push(match addr {
+                Some(addr) => {
+                    self.instruction_breakpoints.push(addr);
+                    json!({ "verified": true, "instructionReference": reference(addr) })
+                },
+                None => json!({ "verified": false, "message": "invalid address" }),
+            });
+        }
+
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    // a frame for the current pc then one for each return address
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let cpu = self.debugger()?.cpu();
+        let addrs: Vec<u16> = std::iter::once(cpu.get_pc())
+            .chain(cpu.stack().entries().iter().rev().copied())
+            .collect();
+
+        let frames: Vec<Value> = addrs.iter().enumerate().map(|(id, &addr)| {
+            let mut frame = json!({
+                "id": id,
+                "name": self.frame_name(addr),
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": reference(addr),
+            });
+            if let Some(entry) = self.symbols.line_for_address(addr) {
+                let name = Path::new(&entry.file).file_name().map(|name| name.to_string_lossy().into_owned());
+                frame["source"] = json!({ "name": name, "path": entry.file });
+                frame["line"] = json!(entry.line);
+                frame["column"] = json!(1);
+            }
+            frame
+        }).collect();
+
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    // the closest label at or before `addr`, or just the address
+    fn frame_name(&self, addr: u16) -> String {
+        self.symbols.labels()
+            .filter(|(_, label)| *label <= addr)
+            .max_by_key(|(_, label)| *label)
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_else(|| reference(addr))
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value, String> {
+        let cpu = self.debugger()?.cpu();
+        let byte = |name: String, value: u8| json!({ "name": name, "value": format!("{:#04x}", value), "variablesReference": 0 });
+        let addr = |name: &str, value: u16| json!({
+            "name": name,
+            "value": reference(value),
+            "variablesReference": 0,
+            "memoryReference": reference(value),
+        });
+
+        let variables: Vec<Value> = match args["variablesReference"].as_i64() {
+            Some(REGISTERS_REF) => (0..16)
+                .map(|x| byte(format!("V{:X}", x), cpu.get_v(x)))
+                .chain([addr("I", cpu.get_i()), addr("PC", cpu.get_pc())])
+                .collect(),
+            Some(TIMERS_REF) => vec![
+                byte("DT".to_string(), cpu.get_delay_timer()),
+                byte("ST".to_string(), cpu.get_sound_timer()),
+            ],
+            Some(STACK_REF) => cpu.stack().entries()
+                .iter()
+                .enumerate()
+                .map(|(n, &ret)| addr(&n.to_string(), ret))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or("");
+        let text = args["value"].as_str().unwrap_or("");
+        let value = parse_reference(text).ok_or(format!("invalid value '{}'", text))?;
+        let byte = u8::try_from(value).map_err(|_| format!("{} doesn't fit in a byte", text));
+        let cpu = self.debugger()?.cpu_mut();
+
+        match name {
+            "I" => cpu.set_i(value),
+            "PC" => cpu.go(value),
+            "DT" => cpu.set_delay_timer(byte?),
+            "ST" => cpu.set_sound_timer(byte?),
+            _ => {
+                let x = name.strip_prefix('V')
+                    .and_then(|x| u8::from_str_radix(x, 16).ok())
+                    .filter(|&x| x < 16)
+                    .ok_or(format!("{} can't be changed", name))?;
+                cpu.set_v(x, byte?);
+            },
+        }
+
+        let shown = if matches!(name, "I" | "PC") { reference(value) } else { format!("{:#04x}", value) };
+        Ok(json!({ "value": shown }))
+    }
+
+    fn read_memory(&mut self, args: &Value) -> Result<Value, String> {
+        let cpu = self.debugger()?.cpu();
+        let base = args["memoryReference"].as_str().and_then(parse_reference).ok_or("invalid memory reference")?;
+        let start = (base as i64).saturating_add(args["offset"].as_i64().unwrap_or(0));
+
+        // anything outside memory is unreadable, and there's never more to
+        // read than the whole of it
+        let size = cpu.memory_size() as i64;
+        let count = args["count"].as_u64().unwrap_or(0).min(size as u64) as i64;
+        let from = start.clamp(0, size) as usize;
+        let to = start.saturating_add(count).clamp(0, size) as usize;
+        let memory = cpu.read_memory();
+        let data = &memory[from..to.max(from)];
+
+        Ok(json!({
+            "address": format!("{:#06x}", start.max(0)),
+            "data": base64(data),
+            "unreadableBytes": (count as usize).saturating_sub(data.len()),
+        }))
+    }
+}
+
+// load the program a launch request asks for
+fn launch(args: &Value) -> Result<(Debugger, SymbolMap), String> {
+    let program = args["program"].as_str().ok_or("launch needs a program")?;
+    let rom = fs::read(program).map_err(|e| format!("{}: {}", program, e))?;
+    let mut cpu = CPU::new();
+
+    if let Some(profile) = args["quirks"].as_str() {
+        let profile: QuirkProfile = profile.parse()?;
+        cpu.set_quirks(profile.quirks());
+        cpu.resize_memory(profile.memory_size());
+        cpu.set_stack_depth(profile.stack_depth());
+    }
+
+    let load_addr = args["loadAddress"].as_u64().unwrap_or(cpu::PROGRAM_START as u64);
+    let end = usize::try_from(load_addr).ok().and_then(|addr| addr.checked_add(rom.len()));
+    if end.is_none_or(|end| end > cpu.memory_size()) {
+        return Err(format!("{}: doesn't fit in memory at {:#x}", program, load_addr));
+    }
+    cpu.load_rom(load_addr as u16, &rom).map_err(|e| e.to_string())?;
+
+    if let Some(speed) = args["speed"].as_u64() {
+        cpu.set_instructions_per_frame(speed as u32);
+    }
+
+    // symbols are optional, but if asked for by name they have to be there
+    let symbols = match args["symbols"].as_str() {
+        Some(path) => SymbolMap::load(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?,
+        None => SymbolMap::load(&symbols::path_for_rom(Path::new(program))).unwrap_or_default(),
+    };
+
+    Ok((Debugger::new(cpu), symbols))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // a file that only this test uses
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rschip8-dap-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn request(seq: i64, command: &str, arguments: Value) -> Value {
+        json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+    }
+
+    // everything the server sends back for `requests`
+    fn serve(requests: Vec<Value>) -> Vec<Value> {
+        let (tx, rx) = mpsc::channel();
+        requests.into_iter().for_each(|message| tx.send(message).unwrap());
+        drop(tx);
+
+        let mut server = DapServer::new(Vec::new(), rx);
+        server.run().unwrap();
+        let mut out = &server.out[..];
+        std::iter::from_fn(|| read_message(&mut out).unwrap()).collect()
+    }
+
+    #[test]
+    fn framing() {
+        let mut out = Vec::new();
+        write_message(&mut out, &json!({ "command": "threads" })).unwrap();
+        assert_eq!(out, b"Content-Length: 21\r\n\r\n{\"command\":\"threads\"}".as_ref());
+
+        let mut input = &b"Content-Type: x\r\nContent-Length: 2\r\n\r\n{}"[..];
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({})));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+        assert!(read_message(&mut &b"\r\n{}"[..]).is_err());
+    }
+
+    #[test]
+    fn references_and_base64() {
+        assert_eq!(parse_reference("0x200"), Some(0x200));
+        assert_eq!(parse_reference("512"), Some(0x200));
+        assert_eq!(parse_reference("0x10000"), None);
+        assert_eq!(base64(b"CHIP-8"), "Q0hJUC04");
+        assert_eq!(base64(b"ab"), "YWI=");
+    }
+
+    #[test]
+    fn unknown_requests_fail() {
+        let messages = serve(vec![request(1, "frobnicate", json!({})), request(2, "stackTrace", json!({}))]);
+        assert_eq!(messages[0]["success"], false);
+        assert_eq!(messages[0]["message"], "unsupported request 'frobnicate'");
+        assert_eq!(messages[1]["request_seq"], 2);
+        assert_eq!(messages[1]["message"], "no program has been launched");
+    }
+
+    #[test]
+    fn breakpoints_set_before_launch() {
+        let rom = temp_file("early.ch8", &[0x00, 0xe0, 0x00, 0xe0, 0x12, 0x04]);
+        let source = "/nowhere/early.8o";
+        let symbols = temp_file("early.sym", format!("line 0204 10 {}\n", source).as_bytes());
+        let messages = serve(vec![
+            request(1, "initialize", json!({})),
+            request(2, "setBreakpoints", json!({ "source": { "path": source }, "breakpoints": [{ "line": 9 }] })),
+            request(3, "launch", json!({ "program": rom, "symbols": symbols })),
+            request(4, "configurationDone", json!({})),
+            request(5, "stackTrace", json!({})),
+        ]);
+        fs::remove_file(rom).unwrap();
+        fs::remove_file(symbols).unwrap();
+
+        let kinds: Vec<&str> = messages.iter()
+            .map(|message| message["command"].as_str().or_else(|| message["event"].as_str()).unwrap())
+            .collect();
+        assert_eq!(kinds, [
+            "initialize", "initialized", "setBreakpoints", "launch", "breakpoint", "configurationDone", "stopped",
+            "stackTrace",
+        ]);
+
+        let breakpoint = &messages[2]["body"]["breakpoints"][0];
+        assert_eq!(breakpoint["verified"], false);
+        assert_eq!(messages[4]["body"], json!({ "reason": "changed", "breakpoint": {
+            "id": breakpoint["id"],
+            "verified": true,
+            "line": 10,
+            "instructionReference": "0x0204",
+        } }));
+        assert_eq!(messages[6]["body"]["reason"], "breakpoint");
+        assert_eq!(messages[7]["body"]["stackFrames"][0]["line"], 10);
+    }
+
+    #[test]
+    fn read_memory() {
+        let rom = temp_file("read.ch8", &[0x60, 0x01, 0x12, 0x02]);
+        let messages = serve(vec![
+            request(1, "launch", json!({ "program": rom })),
+            request(2, "readMemory", json!({ "memoryReference": "0x200", "count": 4 })),
+            request(3, "readMemory", json!({ "memoryReference": "0xffe", "count": 9223372036854775800u64 })),
+            request(4, "readMemory", json!({ "memoryReference": "0", "offset": i64::MIN, "count": 2 })),
+            request(5, "readMemory", json!({ "memoryReference": "0xffff", "offset": i64::MAX, "count": 2 })),
+        ]);
+        fs::remove_file(rom).unwrap();
+
+        assert_eq!(messages[1]["body"], json!({ "address": "0x0200", "data": "YAESAg==", "unreadableBytes": 0 }));
+        assert_eq!(messages[2]["body"]["data"], "AAA=");
+        assert_eq!(messages[2]["body"]["unreadableBytes"], 4094);
+        assert_eq!(messages[3]["body"]["unreadableBytes"], 2);
+        assert_eq!(messages[4]["body"]["unreadableBytes"], 2);
+    }
+
+    #[test]
+    fn launch_address_out_of_range() {
+        let rom = temp_file("load.ch8", &[0x00, 0xe0]);
+        let messages = serve(vec![
+            request(1, "launch", json!({ "program": rom, "loadAddress": u64::MAX })),
+            request(2, "launch", json!({ "program": rom, "loadAddress": 0xfff })),
+        ]);
+        fs::remove_file(&rom).unwrap();
+
+        for message in messages {
+            assert_eq!(message["success"], false);
+            assert!(message["message"].as_str().unwrap().contains("doesn't fit in memory at 0x"), "{}", message);
+        }
+    }
+}
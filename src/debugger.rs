@@ -0,0 +1,243 @@
+use std::collections::BTreeSet;
+
+use crate::cpu::{CpuError, CPU};
+use crate::instruction::Instruction;
+
+// how often a long running continue checks whether it's been interrupted
+const INTERRUPT_CHECK_INSTRUCTIONS: u32 = 1000;
+
+// why execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // finished the step that was asked for
+    Step,
+    // reached a breakpoint, pc is at the breakpoint's address
+    Breakpoint,
+    // 00FD, nothing more will run
+    Exited,
+    // whoever was driving asked us to stop
+    Interrupted,
+}
+
+// runs a CPU an instruction at a time with breakpoints, the core of the
+// command line, gdb and DAP debuggers. timers tick every
+// instructions_per_frame instructions the same as run_frame would
+#[derive(Debug)]
+pub struct Debugger {
+    cpu: CPU,
+    breakpoints: BTreeSet<u16>,
+    // instructions run so far this frame
+    cycles: u32,
+}
+
+impl Debugger {
+    pub fn new(cpu: CPU) -> Self {
+        Debugger { cpu, breakpoints: BTreeSet::new(), cycles: 0 }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    // returns false if there was already one there
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    // returns false if there wasn't one there
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // the instruction at pc, if there's a valid one
+    pub fn current_instruction(&self) -> Option<Instruction> {
+        let pc = self.cpu.get_pc();
+        let opcode = self.cpu.read_word(pc).ok()?;
+        let next = if opcode == 0xf000 { self.cpu.read_word(pc.wrapping_add(2)).ok()? } else { 0 };
+        Instruction::decode_long(opcode, next).ok()
+    }
+
+    // run a single instruction. on error pc is left at the instruction that
+    // failed
+    pub fn step(&mut self) -> Result<Stop, CpuError> {
+        if self.cpu.has_exited() {
+            return Ok(Stop::Exited);
+        }
+
+        self.cpu.clock()?;
+        self.cycles += 1;
+        if self.cycles >= self.cpu.get_instructions_per_frame() || self.cpu.is_waiting_for_vblank() {
+            self.cpu.end_frame();
+            self.cycles = 0;
+        }
+
+        Ok(if self.cpu.has_exited() { Stop::Exited } else { Stop::Step })
+    }
+
+    // step, but run a CALL all the way through to its return
+    pub fn next(&mut self, interrupted: impl FnMut() -> bool) -> Result<Stop, CpuError> {
+        match self.current_instruction() {
+            Some(Instruction::Call(_)) => {
+                let ret = self.cpu.get_pc().wrapping_add(2);
+                let depth = self.cpu.stack().len();
+                // checking the depth stops a recursive call returning to an
+                // outer copy of itself from counting
+                self.run_until(interrupted, |cpu| cpu.get_pc() == ret && cpu.stack().len() <= depth)
+            },
+            _ => self.step(),
+        }
+    }
+
+    // run until a breakpoint, the program exits or `interrupted` returns true
+    pub fn cont(&mut self, interrupted: impl FnMut() -> bool) -> Result<Stop, CpuError> {
+        self.run_until(interrupted, |_| false)
+    }
+
+    // run until `done` returns true, stopping early like cont does. the
+    // first instruction always runs so that continuing from a breakpoint
+    // doesn't stop straight away
+    pub fn run_until(
+        &mut self,
+        mut interrupted: impl FnMut() -> bool,
+        mut done: impl FnMut(&CPU) -> bool,
+    ) -> Result<Stop, CpuError> {
+        let mut count = 0;
+        loop {
+            if self.step()? == Stop::Exited {
+                return Ok(Stop::Exited);
+            }
+            if done(&self.cpu) {
+                return Ok(Stop::Step);
+            }
+            if self.breakpoints.contains(&self.cpu.get_pc()) {
+                return Ok(Stop::Breakpoint);
+            }
+
+            count += 1;
+            if count % INTERRUPT_CHECK_INSTRUCTIONS == 0 && interrupted() {
+                return Ok(Stop::Interrupted);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::PROGRAM_START;
+
+    // CALL 0x206, ADD V1,1, EXIT, then the subroutine ADD V0,1, RET
+    const PROGRAM: [u16; 5] = [0x2206, 0x7101, 0x00fd, 0x7001, 0x00ee];
+
+    fn debugger(program: &[u16]) -> Debugger {
+        let mut cpu = CPU::new();
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        cpu.load_rom(PROGRAM_START, &rom).unwrap();
+        Debugger::new(cpu)
+    }
+
+    fn never() -> bool {
+        false
+    }
+
+    #[test]
+    fn step_until_exit() {
+        let mut debugger = debugger(&PROGRAM);
+        assert_eq!(debugger.current_instruction(), Some(Instruction::Call(0x206)));
+
+        let mut pcs = Vec::new();
+        while debugger.step().unwrap() == Stop::Step {
+            pcs.push(debugger.cpu().get_pc());
+        }
+        assert_eq!(pcs, [0x206, 0x208, 0x202, 0x204]);
+        assert_eq!((debugger.cpu().get_v(0), debugger.cpu().get_v(1)), (1, 1));
+
+        // nothing more runs once it's exited
+        assert_eq!(debugger.step(), Ok(Stop::Exited));
+        assert_eq!(debugger.cont(never), Ok(Stop::Exited));
+    }
+
+    #[test]
+    fn next_runs_calls_through() {
+        let mut debugger = debugger(&PROGRAM);
+        assert_eq!(debugger.next(never), Ok(Stop::Step));
+        assert_eq!(debugger.cpu().get_pc(), 0x202);
+        assert_eq!(debugger.cpu().get_v(0), 1);
+        assert!(debugger.cpu().stack().is_empty());
+
+        // anything else is a single step
+        assert_eq!(debugger.next(never), Ok(Stop::Step));
+        assert_eq!(debugger.cpu().get_pc(), 0x204);
+        assert_eq!(debugger.next(never), Ok(Stop::Exited));
+
+        // a breakpoint inside the call still stops it
+        let mut debugger = self::debugger(&PROGRAM);
+        debugger.add_breakpoint(0x208);
+        assert_eq!(debugger.next(never), Ok(Stop::Breakpoint));
+        assert_eq!(debugger.cpu().get_pc(), 0x208);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = debugger(&PROGRAM);
+        assert!(debugger.add_breakpoint(0x208));
+        assert!(debugger.add_breakpoint(0x202));
+        assert!(!debugger.add_breakpoint(0x208));
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x202, 0x208]);
+
+        assert_eq!(debugger.cont(never), Ok(Stop::Breakpoint));
+        assert_eq!(debugger.cpu().get_pc(), 0x208);
+        // continuing from a breakpoint moves off it first
+        assert_eq!(debugger.cont(never), Ok(Stop::Breakpoint));
+        assert_eq!(debugger.cpu().get_pc(), 0x202);
+
+        assert!(debugger.remove_breakpoint(0x202));
+        assert!(!debugger.remove_breakpoint(0x202));
+        debugger.clear_breakpoints();
+        assert_eq!(debugger.breakpoints().count(), 0);
+        assert_eq!(debugger.cont(never), Ok(Stop::Exited));
+    }
+
+    #[test]
+    fn interrupted() {
+        // JP 0x200 forever
+        let mut debugger = debugger(&[0x1200]);
+        let mut checks = 0;
+        let stop = debugger.cont(|| {
+            checks += 1;
+            checks == 3
+        });
+        assert_eq!(stop, Ok(Stop::Interrupted));
+        assert_eq!(checks, 3);
+        assert_eq!(debugger.cpu().get_pc(), 0x200);
+    }
+
+    #[test]
+    fn errors_stop_at_the_instruction() {
+        let mut debugger = debugger(&[0x6001, 0x00ee]);
+        assert_eq!(debugger.cont(never), Err(CpuError::StackUnderflow));
+        assert_eq!(debugger.cpu().get_pc(), 0x202);
+        assert_eq!(debugger.current_instruction(), Some(Instruction::Ret));
+    }
+
+    #[test]
+    fn long_instructions() {
+        let mut debugger = debugger(&[0xf000, 0x1234]);
+        assert_eq!(debugger.current_instruction(), Some(Instruction::LdILong(0x1234)));
+        debugger.step().unwrap();
+        assert_eq!(debugger.cpu().get_i(), 0x1234);
+        assert_eq!(debugger.cpu().get_pc(), 0x204);
+    }
+}
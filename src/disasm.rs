@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::instruction::Instruction;
+
+// how many bytes go on each line of data
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    // the same mnemonics as Instruction's Display, LD V0, #12 and so on
+    Classic,
+    // Octo, v0 := 0x12
+    Octo,
+}
+
+impl Syntax {
+    // the syntax a source file is in going by its extension. only .chp and
+    // .c8 are taken to be Chipper, everything else is Octo
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("chp") | Some("c8") => Syntax::Classic,
+            _ => Syntax::Octo,
+        }
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" | "chipper" => Ok(Syntax::Classic),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("unknown syntax '{}'", s)),
+        }
+    }
+}
+
+// why an address got a label, in order of preference when there's more
+// than one reason
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Entry,
+    Sub,
+    Table,
+    Jump,
+    Data,
+}
+
+impl LabelKind {
+    fn name(&self, addr: u16) -> String {
+        match self {
+            LabelKind::Entry => "main".to_string(),
+            LabelKind::Sub => format!("sub_{:03x}", addr),
+            LabelKind::Table => format!("table_{:03x}", addr),
+            LabelKind::Jump => format!("label_{:03x}", addr),
+            LabelKind::Data => format!("data_{:03x}", addr),
+        }
+    }
+}
+
+// a ROM split into code and data by following every path from the entry
+// point, with labels for everywhere that's jumped to, called or pointed at
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    base: u16,
+    rom: Vec<u8>,
+    code: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl Disassembly {
+    // `rom` is loaded at `base`, which is also where execution starts
+    pub fn new(rom: &[u8], base: u16) -> Self {
+        let mut disassembly = Disassembly {
+            base,
+            rom: rom.to_vec(),
+            code: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembly.walk();
+        disassembly
+    }
+
+    fn end(&self) -> u32 {
+        self.base as u32 + self.rom.len() as u32
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.base && (addr as u32) < self.end()
+    }
+
+    fn word(&self, addr: u16) -> Option<u16> {
+        let offset = addr.checked_sub(self.base)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn decode(&self, addr: u16) -> Option<Instruction> {
+        let opcode = self.word(addr)?;
+        let next = if opcode == 0xf000 { self.word(addr.wrapping_add(2))? } else { 0 };
+        Instruction::decode_long(opcode, next).ok()
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = (u16, &Instruction)> {
+        self.code.iter().map(|(addr, insn)| (*addr, insn))
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    fn walk(&mut self) {
+        use Instruction::*;
+
+        let mut kinds: BTreeMap<u16, LabelKind> = BTreeMap::new();
+        let mut mark = |addr: u16, kind: LabelKind| {
+            let entry = kinds.entry(addr).or_insert(kind);
+            *entry = (*entry).min(kind);
+        };
+        mark(self.base, LabelKind::Entry);
+
+        // bytes already known to be code, so a path running into the middle
+        // of an instruction stops rather than decoding it twice
+        let mut claimed = BTreeSet::new();
+        let mut todo = vec![self.base];
+
+        while let Some(addr) = todo.pop() {
+            if self.code.contains_key(&addr) || claimed.contains(&addr) {
+                continue;
+            }
+            let insn = match self.decode(addr) {
+                Some(insn) => insn,
+                None => continue,
+            };
+            let size = insn.size();
+            if (1..size).any(|i| claimed.contains(&addr.wrapping_add(i))) {
+                continue;
+            }
+
+            self.code.insert(addr, insn);
+            claimed.extend((0..size).map(|i| addr.wrapping_add(i)));
+            let next = addr.wrapping_add(size);
+
+            match insn {
+                Jp(target) => {
+                    mark(target, LabelKind::Jump);
+                    todo.push(target);
+                },
+                Call(target) => {
+                    mark(target, LabelKind::Sub);
+                    todo.extend([target, next]);
+                },
+                // wherever it goes it'll be somewhere after the table start
+                JpV0(target) => {
+                    mark(target, LabelKind::Table);
+                    todo.push(target);
+                },
+                Ret | Exit => {},
+                // skips hop over a whole F000 NNNN like the CPU does
+                Se(..) | Sne(..) | SeReg(..) | SneReg(..) | Skp(_) | Sknp(_) => {
+                    let skip = if self.word(next) == Some(0xf000) { 4 } else { 2 };
+                    todo.extend([next, next.wrapping_add(skip)]);
+                },
+                LdI(target) | LdILong(target) => {
+                    mark(target, LabelKind::Data);
+                    todo.push(next);
+                },
+                _ => todo.push(next),
+            }
+        }
+
+        // only label things we'll actually print a line for, not the
+        // middle of an instruction or somewhere outside the ROM
+        for (addr, kind) in kinds {
+            let starts_line = self.code.contains_key(&addr) || !claimed.contains(&addr);
+            if self.contains(addr) && starts_line {
+                self.labels.insert(addr, kind.name(addr));
+            }
+        }
+    }
+
+    // the instruction in the given syntax, with addresses replaced by labels
+    pub fn format(&self, insn: &Instruction, syntax: Syntax) -> String {
+        match syntax {
+            Syntax::Classic => self.classic(insn),
+            Syntax::Octo => self.octo(insn),
+        }
+    }
+
+    fn classic(&self, insn: &Instruction) -> String {
+        use Instruction::*;
+
+        let label = |addr: u16| self.label(addr);
+        match *insn {
+            Jp(addr) if label(addr).is_some() => format!("JP {}", label(addr).unwrap()),
+            Call(addr) if label(addr).is_some() => format!("CALL {}", label(addr).unwrap()),
+            LdI(addr) if label(addr).is_some() => format!("LD I, {}", label(addr).unwrap()),
+            JpV0(addr) if label(addr).is_some() => format!("JP V0, {}", label(addr).unwrap()),
+            LdILong(addr) if label(addr).is_some() => format!("LD I, LONG {}", label(addr).unwrap()),
+            _ => insn.to_string(),
+        }
+    }
+
+    fn octo(&self, insn: &Instruction) -> String {
+        use Instruction::*;
+
+        let addr = |addr: u16| match self.label(addr) {
+            Some(label) => label.to_string(),
+            None => format!("{:#05x}", addr),
+        };
+        let v = |x: u8| format!("v{:x}", x);
+
+        match *insn {
+            ScrollDown(n) => format!("scroll-down {}", n),
+            ScrollUp(n) => format!("scroll-up {}", n),
+            Cls => "clear".to_string(),
+            Ret => "return".to_string(),
+            ScrollRight => "scroll-right".to_string(),
+            ScrollLeft => "scroll-left".to_string(),
+            Exit => "exit".to_string(),
+            Lores => "lores".to_string(),
+            Hires => "hires".to_string(),
+            // Octo has no SYS, so just the bytes
+            Sys(_) => {
+                let [hi, lo] = insn.encode().to_be_bytes();
+                format!("{:#04x} {:#04x}", hi, lo)
+            },
+            Jp(target) => format!("jump {}", addr(target)),
+            Call(target) => match self.label(target) {
+                Some(label) => label.to_string(),
+                None => format!(":call {:#05x}", target),
+            },
+            // `if c then` runs the next instruction when c holds, so it's
+            // the opposite of the skip condition
+            Se(x, nn) => format!("if {} != {:#04x} then", v(x), nn),
+            Sne(x, nn) => format!("if {} == {:#04x} then", v(x), nn),
+            SeReg(x, y) => format!("if {} != {} then", v(x), v(y)),
+            SneReg(x, y) => format!("if {} == {} then", v(x), v(y)),
+            Skp(x) => format!("if {} -key then", v(x)),
+            Sknp(x) => format!("if {} key then", v(x)),
+            SaveRange(x, y) => format!("save {} - {}", v(x), v(y)),
+            LoadRange(x, y) => format!("load {} - {}", v(x), v(y)),
+            Ld(x, nn) => format!("{} := {:#04x}", v(x), nn),
+            Add(x, nn) => format!("{} += {:#04x}", v(x), nn),
+            LdReg(x, y) => format!("{} := {}", v(x), v(y)),
+            Or(x, y) => format!("{} |= {}", v(x), v(y)),
+            And(x, y) => format!("{} &= {}", v(x), v(y)),
+            Xor(x, y) => format!("{} ^= {}", v(x), v(y)),
+            AddReg(x, y) => format!("{} += {}", v(x), v(y)),
+            Sub(x, y) => format!("{} -= {}", v(x), v(y)),
+            Shr(x, y) => format!("{} >>= {}", v(x), v(y)),
+            Subn(x, y) => format!("{} =- {}", v(x), v(y)),
+            Shl(x, y) => format!("{} <<= {}", v(x), v(y)),
+            LdI(target) => format!("i := {}", addr(target)),
+            JpV0(target) => format!("jump0 {}", addr(target)),
+            Rnd(x, nn) => format!("{} := random {:#04x}", v(x), nn),
+            Drw(x, y, n) => format!("sprite {} {} {}", v(x), v(y), n),
+            LdILong(target) => match self.label(target) {
+                Some(label) => format!("i := long {}", label),
+                None => format!("i := long {:#06x}", target),
+            },
+            Plane(n) => format!("plane {}", n),
+            Audio => "audio".to_string(),
+            GetDelay(x) => format!("{} := delay", v(x)),
+            WaitKey(x) => format!("{} := key", v(x)),
+            SetDelay(x) => format!("delay := {}", v(x)),
+            SetSound(x) => format!("buzzer := {}", v(x)),
+            AddI(x) => format!("i += {}", v(x)),
+            LdFont(x) => format!("i := hex {}", v(x)),
+            LdBigFont(x) => format!("i := bighex {}", v(x)),
+            Bcd(x) => format!("bcd {}", v(x)),
+            Pitch(x) => format!("pitch := {}", v(x)),
+            StoreRegs(x) => format!("save {}", v(x)),
+            LoadRegs(x) => format!("load {}", v(x)),
+            SaveFlags(x) => format!("saveflags {}", v(x)),
+            LoadFlags(x) => format!("loadflags {}", v(x)),
+        }
+    }
+
+    // the whole ROM as source that assembles back to the same bytes
+    pub fn write(&self, out: &mut impl Write, syntax: Syntax) -> io::Result<()> {
+        let comment = match syntax {
+            Syntax::Classic => ";",
+            Syntax::Octo => "#",
+        };
+
+        if self.base != 0x200 {
+            match syntax {
+                Syntax::Classic => writeln!(out, "    ORG #{:03X}", self.base)?,
+                Syntax::Octo => writeln!(out, ":org {:#05x}", self.base)?,
+            }
+        }
+
+        let mut addr = self.base;
+        let mut data = Vec::new();
+        while (addr as u32) < self.end() {
+            if let Some(label) = self.label(addr) {
+                self.write_data(out, syntax, &mut data)?;
+                match syntax {
+                    Syntax::Classic => writeln!(out, "{}:", label)?,
+                    Syntax::Octo => writeln!(out, ": {}", label)?,
+                }
+            }
+
+            match self.code.get(&addr) {
+                Some(insn) => {
+                    self.write_data(out, syntax, &mut data)?;
+                    let bytes: String = insn.to_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+                    let text = self.format(insn, syntax);
+                    writeln!(out, "    {:<24} {} {:04x}  {}", text, comment, addr, bytes)?;
+                    addr = addr.wrapping_add(insn.size());
+                },
+                None => {
+                    if data.len() == DATA_PER_LINE {
+                        self.write_data(out, syntax, &mut data)?;
+                    }
+                    data.push((addr, self.rom[(addr - self.base) as usize]));
+                    addr = addr.wrapping_add(1);
+                },
+            }
+
+            // the last instruction could wrap addr back round to 0
+            if addr == 0 {
+                break;
+            }
+        }
+
+        self.write_data(out, syntax, &mut data)
+    }
+
+    fn write_data(&self, out: &mut impl Write, syntax: Syntax, data: &mut Vec<(u16, u8)>) -> io::Result<()> {
+        let start = match data.first() {
+            Some((addr, _)) => *addr,
+            None => return Ok(()),
+        };
+
+        let text = match syntax {
+            Syntax::Classic => {
+                let bytes: Vec<String> = data.iter().map(|(_, b)| format!("#{:02X}", b)).collect();
+                format!("DB {}", bytes.join(", "))
+            },
+            Syntax::Octo => {
+                let bytes: Vec<String> = data.iter().map(|(_, b)| format!("{:#04x}", b)).collect();
+                bytes.join(" ")
+            },
+        };
+        let comment = if syntax == Syntax::Classic { ";" } else { "#" };
+        writeln!(out, "    {:<24} {} {:04x}", text, comment, start)?;
+
+        data.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 14] = [
+        0x22, 0x08, // call sub_208
+        0xa2, 0x0c, // i := data_20c
+        0x30, 0x00, // skip if v0 == 0
+        0x12, 0x06, // jump to itself
+        0x00, 0xee, // return
+        0xff, 0xff, // never reached
+        0x12, 0x34, // data
+    ];
+
+    fn source(syntax: Syntax) -> String {
+        let mut out = Vec::new();
+        Disassembly::new(&ROM, 0x200).write(&mut out, syntax).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn follows_control_flow() {
+        let disassembly = Disassembly::new(&ROM, 0x200);
+        let code: Vec<u16> = disassembly.instructions().map(|(addr, _)| addr).collect();
+        assert_eq!(code, [0x200, 0x202, 0x204, 0x206, 0x208]);
+
+        let labels: Vec<Option<&str>> = [0x200, 0x206, 0x208, 0x20a, 0x20c].iter()
+            .map(|&addr| disassembly.label(addr))
+            .collect();
+        assert_eq!(labels, [Some("main"), Some("label_206"), Some("sub_208"), None, Some("data_20c")]);
+    }
+
+    #[test]
+    fn classic() {
+        assert_eq!(source(Syntax::Classic), "\
+main:
+    CALL sub_208             ; 0200  2208
+    LD I, data_20c           ; 0202  a20c
+    SE V0, #00               ; 0204  3000
+label_206:
+    JP label_206             ; 0206  1206
+sub_208:
+    RET                      ; 0208  00ee
+    DB #FF, #FF              ; 020a
+data_20c:
+    DB #12, #34              ; 020c
+");
+    }
+
+    #[test]
+    fn octo() {
+        assert_eq!(source(Syntax::Octo), "\
+: main
+    sub_208                  # 0200  2208
+    i := data_20c            # 0202  a20c
+    if v0 != 0x00 then       # 0204  3000
+: label_206
+    jump label_206           # 0206  1206
+: sub_208
+    return                   # 0208  00ee
+    0xff 0xff                # 020a
+: data_20c
+    0x12 0x34                # 020c
+");
+    }
+
+    #[test]
+    fn syntax_names() {
+        assert_eq!(Syntax::from_path(Path::new("game.CHP")), Syntax::Classic);
+        assert_eq!(Syntax::from_path(Path::new("game.c8")), Syntax::Classic);
+        assert_eq!(Syntax::from_path(Path::new("game.8o")), Syntax::Octo);
+        assert_eq!(Syntax::from_path(Path::new("game")), Syntax::Octo);
+        assert_eq!("chipper".parse(), Ok(Syntax::Classic));
+        assert!("nasm".parse::<Syntax>().is_err());
+    }
+}
@@ -0,0 +1,279 @@
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+// SUPER-CHIP high resolution mode
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// XO-CHIP has two bitplanes, giving four colours
+pub const NUM_PLANES: usize = 2;
+pub const ALL_PLANES: u8 = 0b11;
+
+// framebuffer stored row by row, each pixel holds a bit per plane. plain
+// CHIP-8 only ever touches the first plane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    hires: bool,
+    // the planes that drawing, clearing and scrolling act on
+    planes: u8,
+    pixels: Vec<u8>,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display {
+            width: WIDTH,
+            height: HEIGHT,
+            hires: false,
+            planes: 0b01,
+            pixels: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ALL_PLANES;
+    }
+
+    // switch between 64x32 and 128x64, which also clears every plane
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires { (HIRES_WIDTH, HIRES_HEIGHT) } else { (WIDTH, HEIGHT) };
+        self.width = width;
+        self.height = height;
+        self.hires = hires;
+        self.pixels = vec![0; width * height];
+    }
+
+    // true if the pixel is lit on any plane
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixel(x, y) != 0
+    }
+
+    // the colour of a pixel, 0 to 3 with a bit for each plane
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    // clear the selected planes
+    pub fn clear(&mut self) {
+        let keep = !self.planes;
+        self.pixels.iter_mut().for_each(|p| *p &= keep);
+    }
+
+    // XOR an 8 pixel wide sprite onto the screen. the starting position always
+    // wraps around the screen, anything drawn past the edges is clipped unless
+    // `wrap` is set. returns true if any lit pixel was turned off.
+    //
+    // with more than one plane selected `sprite` holds the data for each plane
+    // one after the other, lowest plane first.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        let rows: Vec<u16> = sprite.iter().map(|&b| (b as u16) << 8).collect();
+        self.draw_planes(x, y, &rows, wrap)
+    }
+
+    // same as draw_sprite but for SUPER-CHIP's 16x16 sprites, which are two
+    // bytes per row
+    pub fn draw_large_sprite(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        let rows: Vec<u16> = sprite
+            .chunks(2)
+            .map(|row| u16::from_be_bytes([row[0], *row.get(1).unwrap_or(&0)]))
+            .collect();
+        self.draw_planes(x, y, &rows, wrap)
+    }
+
+    fn draw_planes(&mut self, x: usize, y: usize, rows: &[u16], wrap: bool) -> bool {
+        let selected: Vec<u8> = (0..NUM_PLANES)
+            .map(|plane| 1 << plane)
+            .filter(|bit| self.planes & bit != 0)
+            .collect();
+        if selected.is_empty() {
+            return false;
+        }
+
+        let per_plane = rows.len() / selected.len();
+        let mut collision = false;
+        for (bit, plane_rows) in selected.iter().zip(rows.chunks(per_plane.max(1))) {
+            collision |= self.blit(x, y, plane_rows, *bit, wrap);
+        }
+        collision
+    }
+
+    // rows are up to 16 pixels wide, MSB leftmost, drawn to plane `bit`
+    fn blit(&mut self, x: usize, y: usize, rows: &[u16], bit: u8, wrap: bool) -> bool {
+        let x = x % self.width;
+        let y = y % self.height;
+        let mut collision = false;
+
+        for (row, bits) in rows.iter().enumerate() {
+            let mut py = y + row;
+            if py >= self.height {
+                if !wrap {
+                    break;
+                }
+                py %= self.height;
+            }
+
+            for col in 0..16 {
+                let mut px = x + col;
+                if px >= self.width {
+                    if !wrap {
+                        break;
+                    }
+                    px %= self.width;
+                }
+
+                if bits & (0x8000 >> col) != 0 {
+                    let pixel = &mut self.pixels[py * self.width + px];
+                    collision |= *pixel & bit != 0;
+                    *pixel ^= bit;
+                }
+            }
+        }
+
+        collision
+    }
+
+    // scrolling moves the selected planes by (dx, dy), blank pixels come in
+    // from the edges
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width as isize, self.height as isize);
+        let mask = self.planes;
+        let old = self.pixels.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let moved = if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                    old[(sy * width + sx) as usize] & mask
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.pixels[(y * width + x) as usize];
+                *pixel = (*pixel & !mask) | moved;
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the lit pixels, as (x, y)
+    fn lit(display: &Display) -> Vec<(usize, usize)> {
+        (0..display.height())
+            .flat_map(|y| (0..display.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| display.get(x, y))
+            .collect()
+    }
+
+    #[test]
+    fn sprites_xor_and_report_collisions() {
+        let mut display = Display::new();
+        assert!(!display.draw_sprite(2, 1, &[0x81, 0x40], false));
+        assert_eq!(lit(&display), [(2, 1), (9, 1), (3, 2)]);
+
+        assert!(display.draw_sprite(2, 1, &[0x80], false));
+        assert_eq!(lit(&display), [(9, 1), (3, 2)]);
+        assert!(!display.draw_sprite(0, 0, &[0x80], false));
+    }
+
+    #[test]
+    fn edges_clip_or_wrap() {
+        // the position wraps either way
+        let mut display = Display::new();
+        display.draw_sprite(WIDTH + 1, HEIGHT, &[0x80], false);
+        assert_eq!(lit(&display), [(1, 0)]);
+
+        let mut display = Display::new();
+        display.draw_sprite(62, 31, &[0xf0, 0xf0], false);
+        assert_eq!(lit(&display), [(62, 31), (63, 31)]);
+
+        let mut display = Display::new();
+        display.draw_sprite(62, 31, &[0xe0, 0x80], true);
+        assert_eq!(lit(&display), [(62, 0), (0, 31), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn clear() {
+        let mut display = Display::new();
+        display.draw_sprite(0, 0, &[0xff; 15], false);
+        display.clear();
+        assert!(lit(&display).is_empty());
+    }
+
+    #[test]
+    fn hires_and_large_sprites() {
+        let mut display = Display::new();
+        display.draw_sprite(0, 0, &[0x80], false);
+        display.set_hires(true);
+        assert_eq!((display.width(), display.height()), (HIRES_WIDTH, HIRES_HEIGHT));
+        assert!(lit(&display).is_empty());
+
+        let mut sprite = [0; 32];
+        sprite[0] = 0x80;
+        sprite[31] = 0x01;
+        assert!(!display.draw_large_sprite(100, 40, &sprite, false));
+        assert_eq!(lit(&display), [(100, 40), (115, 55)]);
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut display = Display::new();
+        display.draw_sprite(4, 4, &[0x80], false);
+        display.scroll_down(3);
+        display.scroll_right(4);
+        assert_eq!(lit(&display), [(8, 7)]);
+        display.scroll_up(2);
+        display.scroll_left(4);
+        assert_eq!(lit(&display), [(4, 5)]);
+
+        // and off the edge
+        display.scroll_up(6);
+        assert!(lit(&display).is_empty());
+    }
+}
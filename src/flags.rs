@@ -0,0 +1,123 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// the HP-48 only had 8 RPL flags, XO-CHIP allows 16
+pub const NUM_FLAGS: usize = 16;
+
+pub type Flags = [u8; NUM_FLAGS];
+
+// somewhere for FX75/FX85 to keep the RPL flags. on the HP-48 these
+// survived between runs, and games use them for high scores
+pub trait FlagStorage: fmt::Debug {
+    fn load(&mut self) -> io::Result<Flags>;
+    fn save(&mut self, flags: &Flags) -> io::Result<()>;
+}
+
+// flags that only last as long as the CPU does
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFlagStorage {
+    flags: Flags,
+}
+
+impl MemoryFlagStorage {
+    pub fn new() -> Self {
+        MemoryFlagStorage { flags: [0; NUM_FLAGS] }
+    }
+}
+
+impl FlagStorage for MemoryFlagStorage {
+    fn load(&mut self) -> io::Result<Flags> {
+        Ok(self.flags)
+    }
+
+    fn save(&mut self, flags: &Flags) -> io::Result<()> {
+        self.flags = *flags;
+        Ok(())
+    }
+}
+
+// flags kept in a file, which doesn't need to exist until they're first
+// saved
+#[derive(Debug, Clone)]
+pub struct FileFlagStorage {
+    path: PathBuf,
+}
+
+impl FileFlagStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileFlagStorage { path: path.into() }
+    }
+
+    // a file in `dir` named after the hash of `rom`, so every ROM gets its
+    // own flags no matter where it's loaded from
+    pub fn for_rom(dir: &Path, rom: &[u8]) -> Self {
+        Self::new(dir.join(format!("{:016x}.flags", rom_hash(rom))))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl FlagStorage for FileFlagStorage {
+    fn load(&mut self) -> io::Result<Flags> {
+        let mut flags = [0; NUM_FLAGS];
+
+        match fs::read(&self.path) {
+            Ok(data) => {
+                let len = data.len().min(NUM_FLAGS);
+                flags[..len].copy_from_slice(&data[..len]);
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+
+        Ok(flags)
+    }
+
+    fn save(&mut self, flags: &Flags) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, flags)
+    }
+}
+
+// 64 bit FNV-1a, plenty to tell ROMs apart
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_hashes() {
+        assert_eq!(rom_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(rom_hash(b"a"), 0xaf63dc4c8601ec8c);
+        let path = FileFlagStorage::for_rom(Path::new("flags"), b"a").path().to_path_buf();
+        assert_eq!(path, Path::new("flags").join("af63dc4c8601ec8c.flags"));
+    }
+
+    #[test]
+    fn file_storage() {
+        let dir = std::env::temp_dir().join(format!("rschip8-flags-{}", std::process::id()));
+        let mut storage = FileFlagStorage::for_rom(&dir, &[0x00, 0xe0]);
+        assert_eq!(storage.load().unwrap(), [0; NUM_FLAGS]);
+
+        let mut flags = [0; NUM_FLAGS];
+        flags[..3].copy_from_slice(&[1, 2, 3]);
+        storage.save(&flags).unwrap();
+        assert_eq!(FileFlagStorage::for_rom(&dir, &[0x00, 0xe0]).load().unwrap(), flags);
+
+        // short files are padded out with zeros
+        fs::write(storage.path(), [9]).unwrap();
+        assert_eq!(storage.load().unwrap()[..2], [9, 0]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
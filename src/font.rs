@@ -0,0 +1,138 @@
+// each hex digit glyph is 5 rows of 4 pixels, stored in the high nibble
+pub const GLYPH_SIZE: usize = 5;
+pub const FONT_SIZE: usize = GLYPH_SIZE * 16;
+
+// where CPU::new puts the font, just after the jump to 0x200 at the very
+// start of the interpreter area
+pub const DEFAULT_FONT_BASE: u16 = 0x10;
+
+pub type Font = [u8; FONT_SIZE];
+
+// SUPER-CHIP's large font for FX30, 10 rows of 8 pixels per digit
+pub const BIG_GLYPH_SIZE: usize = 10;
+pub const BIG_FONT_SIZE: usize = BIG_GLYPH_SIZE * 16;
+
+// straight after the small font
+pub const DEFAULT_BIG_FONT_BASE: u16 = DEFAULT_FONT_BASE + FONT_SIZE as u16;
+
+pub type BigFont = [u8; BIG_FONT_SIZE];
+
+// the font most modern interpreters use
+pub const STANDARD: Font = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+    0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+    0x90, 0x90, 0xf0, 0x10, 0x10, // 4
+    0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+    0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+    0xf0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+    0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+    0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+    0xe0, 0x90, 0xe0, 0x90, 0xe0, // B
+    0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+    0xe0, 0x90, 0x90, 0x90, 0xe0, // D
+    0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+    0xf0, 0x80, 0xf0, 0x80, 0x80, // F
+];
+
+// the font from the COSMAC VIP interpreter ROM
+pub const VIP: Font = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+    0xf0, 0x10, 0x70, 0x10, 0xf0, // 3
+    0xa0, 0xa0, 0xf0, 0x20, 0x20, // 4
+    0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+    0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+    0xf0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+    0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+    0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+    0xf0, 0x50, 0x70, 0x50, 0xf0, // B
+    0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+    0xf0, 0x50, 0x50, 0x50, 0xf0, // D
+    0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+    0xf0, 0x80, 0xf0, 0x80, 0x80, // F
+];
+
+// the DREAM 6800's 3 pixel wide font
+pub const DREAM_6800: Font = [
+    0xe0, 0xa0, 0xa0, 0xa0, 0xe0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xe0, 0x20, 0xe0, 0x80, 0xe0, // 2
+    0xe0, 0x20, 0xe0, 0x20, 0xe0, // 3
+    0x80, 0xa0, 0xa0, 0xe0, 0x20, // 4
+    0xe0, 0x80, 0xe0, 0x20, 0xe0, // 5
+    0xe0, 0x80, 0xe0, 0xa0, 0xe0, // 6
+    0xe0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xe0, 0xa0, 0xe0, 0xa0, 0xe0, // 8
+    0xe0, 0xa0, 0xe0, 0x20, 0xe0, // 9
+    0xe0, 0xa0, 0xe0, 0xa0, 0xa0, // A
+    0xc0, 0xa0, 0xe0, 0xa0, 0xc0, // B
+    0xe0, 0x80, 0x80, 0x80, 0xe0, // C
+    0xc0, 0xa0, 0xa0, 0xa0, 0xc0, // D
+    0xe0, 0x80, 0xe0, 0x80, 0xe0, // E
+    0xe0, 0x80, 0xc0, 0x80, 0x80, // F
+];
+
+// the ETI-660's font, also 3 pixels wide
+pub const ETI_660: Font = [
+    0xe0, 0xa0, 0xa0, 0xa0, 0xe0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xe0, 0x20, 0xe0, 0x80, 0xe0, // 2
+    0xe0, 0x20, 0xe0, 0x20, 0xe0, // 3
+    0xa0, 0xa0, 0xe0, 0x20, 0x20, // 4
+    0xe0, 0x80, 0xe0, 0x20, 0xe0, // 5
+    0xe0, 0x80, 0xe0, 0xa0, 0xe0, // 6
+    0xe0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xe0, 0xa0, 0xe0, 0xa0, 0xe0, // 8
+    0xe0, 0xa0, 0xe0, 0x20, 0xe0, // 9
+    0xe0, 0xa0, 0xe0, 0xa0, 0xa0, // A
+    0x80, 0x80, 0xe0, 0xa0, 0xe0, // B
+    0xe0, 0x80, 0x80, 0x80, 0xe0, // C
+    0x20, 0x20, 0xe0, 0xa0, 0xe0, // D
+    0xe0, 0x80, 0xe0, 0x80, 0xe0, // E
+    0xe0, 0x80, 0xc0, 0x80, 0x80, // F
+];
+
+// the digits from SUPER-CHIP 1.1, which only went up to 9, with A-F drawn
+// to match
+pub const SCHIP_BIG: BigFont = [
+    0x3c, 0x7e, 0xe7, 0xc3, 0xc3, 0xc3, 0xc3, 0xe7, 0x7e, 0x3c, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3c, // 1
+    0x3e, 0x7f, 0xc3, 0x06, 0x0c, 0x18, 0x30, 0x60, 0xff, 0xff, // 2
+    0x3c, 0x7e, 0xc3, 0x03, 0x0e, 0x0e, 0x03, 0xc3, 0x7e, 0x3c, // 3
+    0x06, 0x0e, 0x1e, 0x36, 0x66, 0xc6, 0xff, 0xff, 0x06, 0x06, // 4
+    0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfe, 0x03, 0xc3, 0x7e, 0x3c, // 5
+    0x3e, 0x7c, 0xe0, 0xc0, 0xfc, 0xfe, 0xc3, 0xc3, 0x7e, 0x3c, // 6
+    0xff, 0xff, 0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3c, 0x7e, 0xc3, 0xc3, 0x7e, 0x7e, 0xc3, 0xc3, 0x7e, 0x3c, // 8
+    0x3c, 0x7e, 0xc3, 0xc3, 0x7f, 0x3f, 0x03, 0x03, 0x3e, 0x7c, // 9
+    0x3c, 0x7e, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, // A
+    0xfc, 0xfe, 0xc3, 0xc3, 0xfe, 0xfe, 0xc3, 0xc3, 0xfe, 0xfc, // B
+    0x3c, 0x7e, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0x7e, 0x3c, // C
+    0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc, // D
+    0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfc, 0xc0, 0xc0, 0xff, 0xff, // E
+    0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfc, 0xc0, 0xc0, 0xc0, 0xc0, // F
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontSet {
+    Standard,
+    Vip,
+    Dream6800,
+    Eti660,
+}
+
+impl FontSet {
+    pub fn glyphs(&self) -> &'static Font {
+        match self {
+            FontSet::Standard => &STANDARD,
+            FontSet::Vip => &VIP,
+            FontSet::Dream6800 => &DREAM_6800,
+            FontSet::Eti660 => &ETI_660,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::{self, Write};
+
+use crate::cpu::CPU;
+use crate::screenshot::Screenshot;
+
+// something that shows the display and feeds the keypad. the runner calls
+// `poll` before every frame and `present` after it
+pub trait Frontend {
+    // handle any input, returning false to stop running
+    fn poll(&mut self, cpu: &mut CPU) -> io::Result<bool>;
+
+    fn present(&mut self, cpu: &CPU) -> io::Result<()>;
+
+    // called once when the runner stops
+    fn finish(&mut self, _cpu: &CPU) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// no input and no output until the end, where the final screen is saved as a
+// PNG or, failing that, dumped as text
+#[derive(Debug)]
+pub struct Headless {
+    screenshot: Option<Screenshot>,
+    frames: u64,
+    // false when stdout is being used for something else, like a recording
+    print: bool,
+}
+
+impl Default for Headless {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Headless {
+    pub fn new(screenshot: Option<Screenshot>) -> Self {
+        Headless { screenshot, frames: 0, print: true }
+    }
+
+    pub fn set_print(&mut self, print: bool) {
+        self.print = print;
+    }
+}
+
+impl Frontend for Headless {
+    fn poll(&mut self, _cpu: &mut CPU) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn present(&mut self, cpu: &CPU) -> io::Result<()> {
+        self.frames += 1;
+        match &self.screenshot {
+            Some(screenshot) => screenshot.frame(cpu.display(), self.frames),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self, cpu: &CPU) -> io::Result<()> {
+        if let Some(screenshot) = &self.screenshot {
+            return screenshot.finish(cpu.display());
+        }
+        if !self.print {
+            return Ok(());
+        }
+
+        let display = cpu.display();
+        let mut out = io::stdout();
+
+        for y in 0..display.height() {
+            let row: String = (0..display.width())
+                .map(|x| if display.get(x, y) { '#' } else { '.' })
+                .collect();
+            writeln!(out, "{}", row)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,486 @@
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use crate::cpu::CpuError;
+use crate::debugger::{Debugger, Stop};
+
+// gdb numbers registers in this order: V0-VF, then I, PC, SP, DT and ST.
+// multi-byte values go over the wire big-endian like CHIP-8 memory
+const NUM_REGISTERS: usize = 21;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+
+// the largest packet we'll accept, told to gdb in qSupported
+const PACKET_SIZE: usize = 0x4000;
+
+// signals for stop replies
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+const SIGINT: u8 = 2;
+
+fn register_size(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+// describes the registers so gdb can name them, there's no CHIP-8
+// architecture built in for it to fall back on
+fn target_xml() -> String {
+    let mut regs = String::new();
+    for x in 0..16 {
+        regs += &format!("  <reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>\n", x);
+    }
+    regs += "  <reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\n";
+    regs += "  <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\n";
+    for name in ["sp", "dt", "st"] {
+        regs += &format!("  <reg name=\"{}\" bitsize=\"8\" type=\"uint8\"/>\n", name);
+    }
+
+    format!(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <feature name=\"org.rschip8.chip8\">\n{}</feature>\n\
+         </target>\n",
+        regs,
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// an odd number of digits fails on the last byte
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+// "addr,len" as used by m, M and qXfer
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+// serves the gdb remote serial protocol for a single connection
+pub struct GdbStub {
+    debugger: Debugger,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // once gdb asks for QStartNoAckMode neither side sends + or -
+    no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn new(debugger: Debugger, stream: TcpStream) -> io::Result<Self> {
+        let writer = stream.try_clone()?;
+        Ok(GdbStub { debugger, reader: BufReader::new(stream), writer, no_ack: false })
+    }
+
+    // handle packets until gdb detaches, kills us or hangs up
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet)? {
+                Some(reply) => self.send(&reply)?,
+                None => return Ok(()),
+            }
+            // the OK still gets acked, it's only after that acks stop
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // the next $packet#cs, acking it unless we've stopped doing that.
+    // None once the connection is closed
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // skip acks and stray interrupts until a packet starts
+            match self.read_byte()? {
+                Some(b'$') => {},
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            if !self.no_ack {
+                if expected != Some(actual) {
+                    self.writer.write_all(b"-")?;
+                    continue;
+                }
+                self.writer.write_all(b"+")?;
+            }
+
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        // $, #, } and * can't appear as they are, } escapes them
+        let mut escaped = Vec::with_capacity(data.len());
+        for &b in data.as_bytes() {
+            if matches!(b, b'$' | b'#' | b'}' | b'*') {
+                escaped.extend_from_slice(&[b'}', b ^ 0x20]);
+            } else {
+                escaped.push(b);
+            }
+        }
+        let checksum = escaped.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+        self.writer.write_all(b"$")?;
+        self.writer.write_all(&escaped)?;
+        write!(self.writer, "#{:02x}", checksum)?;
+        self.writer.flush()?;
+
+        // gdb will ask again if it didn't like it, we don't bother
+        // resending so just swallow the ack
+        if !self.no_ack {
+            self.read_byte()?;
+        }
+        Ok(())
+    }
+
+    // the reply to a packet, or None to close the connection
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let reply = match packet {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => self.read_registers(),
+            "k" => return Ok(None),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            },
+            "QStartNoAckMode" => "OK".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "vCont?" => "vCont;c;s".to_string(),
+            "s" => self.resume(false)?,
+            "c" => self.resume(true)?,
+            _ => self.handle_with_args(packet)?,
+        };
+        Ok(Some(reply))
+    }
+
+    fn handle_with_args(&mut self, packet: &str) -> io::Result<String> {
+        let error = "E01".to_string();
+
+        if packet.starts_with("qSupported") {
+            return Ok(format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+", PACKET_SIZE));
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = match parse_range(range) {
+                Some(range) => range,
+                None => return Ok(error),
+            };
+            let xml = target_xml();
+            let end = offset.saturating_add(len).min(xml.len());
+            let chunk = xml.get(offset.min(end)..end).unwrap_or("");
+            let more = end < xml.len();
+            return Ok(format!("{}{}", if more { "m" } else { "l" }, chunk));
+        }
+        if let Some(action) = packet.strip_prefix("vCont;") {
+            // we only have the one thread, so only the first action matters
+            return match action.chars().next() {
+                Some('s') => self.resume(false),
+                Some('c') => self.resume(true),
+                _ => Ok(error),
+            };
+        }
+        if packet.starts_with('H') {
+            return Ok("OK".to_string());
+        }
+        if let Some(regs) = packet.strip_prefix('G') {
+            return Ok(self.write_registers(regs).unwrap_or(error));
+        }
+        if let Some(reg) = packet.strip_prefix('p') {
+            return Ok(parse_hex(reg).and_then(|reg| self.read_register(reg)).unwrap_or(error));
+        }
+        if let Some(assignment) = packet.strip_prefix('P') {
+            let written = assignment.split_once('=').and_then(|(reg, value)| {
+                self.write_register(parse_hex(reg)?, &unhex(value)?)
+            });
+            return Ok(written.map(|_| "OK".to_string()).unwrap_or(error));
+        }
+        if let Some(range) = packet.strip_prefix('m') {
+            return Ok(parse_range(range).and_then(|(addr, len)| self.read_memory(addr, len)).unwrap_or(error));
+        }
+        if let Some(args) = packet.strip_prefix('M') {
+            let written = args.split_once(':').and_then(|(range, data)| {
+                let (addr, len) = parse_range(range)?;
+                let data = unhex(data).filter(|data| data.len() == len)?;
+                self.write_memory(addr, &data)
+            });
+            return Ok(written.map(|_| "OK".to_string()).unwrap_or(error));
+        }
+        // software and hardware breakpoints are the same thing to us
+        if let Some(args) = packet.strip_prefix("Z0,").or_else(|| packet.strip_prefix("Z1,")) {
+            return Ok(match parse_range(args) {
+                Some((addr, _)) if addr <= u16::MAX as usize => {
+                    self.debugger.add_breakpoint(addr as u16);
+                    "OK".to_string()
+                },
+                _ => error,
+            });
+        }
+        if let Some(args) = packet.strip_prefix("z0,").or_else(|| packet.strip_prefix("z1,")) {
+            return Ok(match parse_range(args) {
+                Some((addr, _)) if addr <= u16::MAX as usize => {
+                    self.debugger.remove_breakpoint(addr as u16);
+                    "OK".to_string()
+                },
+                _ => error,
+            });
+        }
+
+        // an empty reply means we don't support it
+        Ok(String::new())
+    }
+
+    fn register_bytes(&self, reg: usize) -> Vec<u8> {
+        let cpu = self.debugger.cpu();
+        match reg {
+            0..=15 => vec![cpu.get_v(reg as u8)],
+            REG_I => cpu.get_i().to_be_bytes().to_vec(),
+            REG_PC => cpu.get_pc().to_be_bytes().to_vec(),
+            REG_SP => vec![cpu.stack().len() as u8],
+            REG_DT => vec![cpu.get_delay_timer()],
+            REG_ST => vec![cpu.get_sound_timer()],
+            _ => Vec::new(),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..NUM_REGISTERS).map(|reg| hex(&self.register_bytes(reg))).collect()
+    }
+
+    fn read_register(&self, reg: usize) -> Option<String> {
+        if reg >= NUM_REGISTERS {
+            return None;
+        }
+        Some(hex(&self.register_bytes(reg)))
+    }
+
+    // the stack pointer is read only, the stack lives outside memory so
+    // there's nothing sensible to point it at
+    fn write_register(&mut self, reg: usize, value: &[u8]) -> Option<()> {
+        if reg >= NUM_REGISTERS || value.len() != register_size(reg) {
+            return None;
+        }
+
+        let cpu = self.debugger.cpu_mut();
+        match reg {
+            0..=15 => cpu.set_v(reg as u8, value[0]),
+            REG_I => cpu.set_i(u16::from_be_bytes([value[0], value[1]])),
+            REG_PC => cpu.go(u16::from_be_bytes([value[0], value[1]])),
+            REG_SP => if value[0] as usize != cpu.stack().len() {
+                return None;
+            },
+            REG_DT => cpu.set_delay_timer(value[0]),
+            REG_ST => cpu.set_sound_timer(value[0]),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn write_registers(&mut self, regs: &str) -> Option<String> {
+        let bytes = unhex(regs)?;
+        let mut offset = 0;
+        for reg in 0..NUM_REGISTERS {
+            let size = register_size(reg);
+            let value = bytes.get(offset..offset + size)?;
+            // gdb sends the whole set back, so an unchanged sp is fine
+            self.write_register(reg, value)?;
+            offset += size;
+        }
+        Some("OK".to_string())
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Option<String> {
+        let addr = u16::try_from(addr).ok()?;
+        let cpu = self.debugger.cpu();
+        // reads that run off the end get as much as there is, but an empty
+        // reply would mean m isn't supported at all
+        let len = len.min(cpu.memory_size().saturating_sub(addr as usize));
+        if len == 0 {
+            return None;
+        }
+        cpu.read_bytes(addr, len).ok().map(|bytes| hex(&bytes))
+    }
+
+    fn write_memory(&mut self, addr: usize, data: &[u8]) -> Option<()> {
+        let addr = u16::try_from(addr).ok()?;
+        self.debugger.cpu_mut().write_bytes(addr, data).ok()
+    }
+
+    // step or continue, stopping early if gdb sends a ctrl-c (a bare 0x03)
+    // or anything else while we run
+    fn resume(&mut self, cont: bool) -> io::Result<String> {
+        let result = if cont {
+            let reader = &mut self.reader;
+            let interrupted = || interrupt_pending(reader);
+            self.debugger.cont(interrupted)
+        } else {
+            self.debugger.step()
+        };
+
+        Ok(match result {
+            Ok(Stop::Step) => format!("S{:02x}", SIGTRAP),
+            Ok(Stop::Breakpoint) => format!("T{:02x}swbreak:;", SIGTRAP),
+            Ok(Stop::Interrupted) => format!("S{:02x}", SIGINT),
+            Ok(Stop::Exited) => "W00".to_string(),
+            Err(CpuError::InvalidOpcode { .. }) => format!("S{:02x}", SIGILL),
+            Err(_) => format!("S{:02x}", SIGSEGV),
+        })
+    }
+}
+
+// whether the client has sent anything, which stops us so it can be dealt
+// with. a ^C is used up, anything else is left for read_packet
+fn interrupt_pending(reader: &mut BufReader<TcpStream>) -> bool {
+    if reader.buffer().is_empty() {
+        if reader.get_ref().set_nonblocking(true).is_err() {
+            return false;
+        }
+        // would block means nothing's been sent, which is fine
+        let _ = reader.fill_buf();
+        let _ = reader.get_ref().set_nonblocking(false);
+    }
+
+    match reader.buffer().first() {
+        Some(0x03) => {
+            reader.consume(1);
+            true
+        },
+        Some(_) => true,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    use crate::cpu::CPU;
+
+    // a stub with gdb's end of the connection
+    fn connect() -> (GdbStub, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let mut cpu = CPU::new();
+        cpu.load_rom(0x200, &[0x60, 0x2a, 0x12, 0x02]).unwrap();
+        (GdbStub::new(Debugger::new(cpu), stream).unwrap(), client)
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        stub.handle(packet).unwrap().unwrap()
+    }
+
+    #[test]
+    fn packets_are_checked_and_acked() {
+        let (mut stub, mut client) = connect();
+        client.write_all(b"+\x03$qC#b5$qC#b4$m200,2#5d").unwrap();
+        assert_eq!(stub.read_packet().unwrap().as_deref(), Some("qC"));
+        assert_eq!(stub.read_packet().unwrap().as_deref(), Some("m200,2"));
+
+        let mut acks = [0; 2];
+        client.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"-+");
+        // and the + for the m packet
+        client.read_exact(&mut acks[..1]).unwrap();
+        assert_eq!(acks[0], b'+');
+
+        client.write_all(b"+").unwrap();
+        stub.send("a$b").unwrap();
+        let mut sent = [0; 8];
+        client.read_exact(&mut sent).unwrap();
+        assert_eq!(&sent, b"$a}\x04b#44");
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("200,10"), Some((0x200, 0x10)));
+        assert_eq!(parse_range("200"), None);
+        assert_eq!(parse_range("zz,1"), None);
+        assert_eq!(unhex("0aFf"), Some(vec![0x0a, 0xff]));
+        assert_eq!(unhex("0a0"), None);
+    }
+
+    #[test]
+    fn target_xml_reads() {
+        let (mut stub, _client) = connect();
+        let xml = target_xml();
+
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:0,10"), format!("m{}", &xml[..0x10]));
+        assert_eq!(reply(&mut stub, &format!("qXfer:features:read:target.xml:0,{:x}", xml.len())), format!("l{}", xml));
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:10,ffffffffffffffff"), format!("l{}", &xml[0x10..]));
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:ffffffffffffffff,ffffffffffffffff"), "l");
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:10"), "E01");
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let (mut stub, _client) = connect();
+        assert_eq!(reply(&mut stub, "s"), "S05");
+        assert_eq!(reply(&mut stub, "p0"), "2a");
+        assert_eq!(reply(&mut stub, "p11"), "0202");
+        assert_eq!(reply(&mut stub, "p15"), "E01");
+        assert_eq!(&reply(&mut stub, "g")[..4], "2a00");
+
+        assert_eq!(reply(&mut stub, "Pf=07"), "OK");
+        assert_eq!(reply(&mut stub, "Pf=0707"), "E01");
+        assert_eq!(reply(&mut stub, "pf"), "07");
+
+        assert_eq!(reply(&mut stub, "M300,2:abcd"), "OK");
+        assert_eq!(reply(&mut stub, "M300,2:ab"), "E01");
+        assert_eq!(reply(&mut stub, "m300,2"), "abcd");
+        assert_eq!(reply(&mut stub, "mfff,ffffffffffffffff"), "00");
+        assert_eq!(reply(&mut stub, "m10000,1"), "E01");
+        assert_eq!(reply(&mut stub, "qUnknown"), "");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (mut stub, _client) = connect();
+        assert_eq!(reply(&mut stub, "Z0,202,2"), "OK");
+        assert_eq!(reply(&mut stub, "Z1,10000,2"), "E01");
+        assert_eq!(reply(&mut stub, "c"), "T05swbreak:;");
+        assert_eq!(reply(&mut stub, "p11"), "0202");
+        assert_eq!(reply(&mut stub, "z0,202,2"), "OK");
+        assert_eq!(stub.debugger.breakpoints().count(), 0);
+    }
+}
@@ -0,0 +1,393 @@
+use std::error::Error;
+use std::fmt;
+
+// a decoded CHIP-8 instruction, `x` and `y` are register numbers, `nn` an
+// immediate byte, `n` a nibble and `addr` a 12 bit address (16 bits for
+// F000 NNNN)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 00CN, SUPER-CHIP
+    ScrollDown(u8),
+    // 00DN, XO-CHIP
+    ScrollUp(u8),
+    // 00E0
+    Cls,
+    // 00EE
+    Ret,
+    // 00FB, SUPER-CHIP
+    ScrollRight,
+    // 00FC, SUPER-CHIP
+    ScrollLeft,
+    // 00FD, SUPER-CHIP
+    Exit,
+    // 00FE, SUPER-CHIP
+    Lores,
+    // 00FF, SUPER-CHIP
+    Hires,
+    // 0NNN, machine code routine on the original hardware
+    Sys(u16),
+    // 1NNN
+    Jp(u16),
+    // 2NNN
+    Call(u16),
+    // 3XNN
+    Se(u8, u8),
+    // 4XNN
+    Sne(u8, u8),
+    // 5XY0
+    SeReg(u8, u8),
+    // 5XY2, XO-CHIP
+    SaveRange(u8, u8),
+    // 5XY3, XO-CHIP
+    LoadRange(u8, u8),
+    // 6XNN
+    Ld(u8, u8),
+    // 7XNN
+    Add(u8, u8),
+    // 8XY0
+    LdReg(u8, u8),
+    // 8XY1
+    Or(u8, u8),
+    // 8XY2
+    And(u8, u8),
+    // 8XY3
+    Xor(u8, u8),
+    // 8XY4
+    AddReg(u8, u8),
+    // 8XY5
+    Sub(u8, u8),
+    // 8XY6
+    Shr(u8, u8),
+    // 8XY7
+    Subn(u8, u8),
+    // 8XYE
+    Shl(u8, u8),
+    // 9XY0
+    SneReg(u8, u8),
+    // ANNN
+    LdI(u16),
+    // BNNN
+    JpV0(u16),
+    // CXNN
+    Rnd(u8, u8),
+    // DXYN, DXY0 draws a 16x16 sprite
+    Drw(u8, u8, u8),
+    // EX9E
+    Skp(u8),
+    // EXA1
+    Sknp(u8),
+    // F000 NNNN, XO-CHIP. the only four byte instruction
+    LdILong(u16),
+    // FN01, XO-CHIP
+    Plane(u8),
+    // F002, XO-CHIP
+    Audio,
+    // FX07
+    GetDelay(u8),
+    // FX0A
+    WaitKey(u8),
+    // FX15
+    SetDelay(u8),
+    // FX18
+    SetSound(u8),
+    // FX1E
+    AddI(u8),
+    // FX29
+    LdFont(u8),
+    // FX30, SUPER-CHIP
+    LdBigFont(u8),
+    // FX33
+    Bcd(u8),
+    // FX3A, XO-CHIP
+    Pitch(u8),
+    // FX55
+    StoreRegs(u8),
+    // FX65
+    LoadRegs(u8),
+    // FX75, SUPER-CHIP
+    SaveFlags(u8),
+    // FX85, SUPER-CHIP
+    LoadFlags(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError(pub u16);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid opcode {:04x}", self.0)
+    }
+}
+
+impl Error for DecodeError {}
+
+impl Instruction {
+    // decode a two byte instruction. F000 NNNN can't be decoded from its
+    // first word alone so is an error here, use decode_long for that
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        use Instruction::*;
+
+        let x = ((opcode >> 8) & 0xf) as u8;
+        let y = ((opcode >> 4) & 0xf) as u8;
+        let n = (opcode & 0xf) as u8;
+        let nn = (opcode & 0xff) as u8;
+        let addr = opcode & 0xfff;
+
+        let insn = match opcode >> 12 {
+            0x0 => match opcode {
+                0x00c0..=0x00cf => ScrollDown(n),
+                0x00d0..=0x00df => ScrollUp(n),
+                0x00e0 => Cls,
+                0x00ee => Ret,
+                0x00fb => ScrollRight,
+                0x00fc => ScrollLeft,
+                0x00fd => Exit,
+                0x00fe => Lores,
+                0x00ff => Hires,
+                _ => Sys(addr),
+            },
+            0x1 => Jp(addr),
+            0x2 => Call(addr),
+            0x3 => Se(x, nn),
+            0x4 => Sne(x, nn),
+            0x5 => match n {
+                0x0 => SeReg(x, y),
+                0x2 => SaveRange(x, y),
+                0x3 => LoadRange(x, y),
+                _ => return Err(DecodeError(opcode)),
+            },
+            0x6 => Ld(x, nn),
+            0x7 => Add(x, nn),
+            0x8 => match n {
+                0x0 => LdReg(x, y),
+                0x1 => Or(x, y),
+                0x2 => And(x, y),
+                0x3 => Xor(x, y),
+                0x4 => AddReg(x, y),
+                0x5 => Sub(x, y),
+                0x6 => Shr(x, y),
+                0x7 => Subn(x, y),
+                0xe => Shl(x, y),
+                _ => return Err(DecodeError(opcode)),
+            },
+            0x9 if n == 0 => SneReg(x, y),
+            0xa => LdI(addr),
+            0xb => JpV0(addr),
+            0xc => Rnd(x, nn),
+            0xd => Drw(x, y, n),
+            0xe => match nn {
+                0x9e => Skp(x),
+                0xa1 => Sknp(x),
+                _ => return Err(DecodeError(opcode)),
+            },
+            0xf => match nn {
+                0x01 => Plane(x),
+                0x02 if x == 0 => Audio,
+                0x07 => GetDelay(x),
+                0x0a => WaitKey(x),
+                0x15 => SetDelay(x),
+                0x18 => SetSound(x),
+                0x1e => AddI(x),
+                0x29 => LdFont(x),
+                0x30 => LdBigFont(x),
+                0x33 => Bcd(x),
+                0x3a => Pitch(x),
+                0x55 => StoreRegs(x),
+                0x65 => LoadRegs(x),
+                0x75 => SaveFlags(x),
+                0x85 => LoadFlags(x),
+                _ => return Err(DecodeError(opcode)),
+            },
+            _ => return Err(DecodeError(opcode)),
+        };
+
+        Ok(insn)
+    }
+
+    // decode an instruction given its first word and the one after it, which
+    // is only used by F000 NNNN
+    pub fn decode_long(opcode: u16, next: u16) -> Result<Instruction, DecodeError> {
+        match opcode {
+            0xf000 => Ok(Instruction::LdILong(next)),
+            _ => Self::decode(opcode),
+        }
+    }
+
+    // size in bytes
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    // the full encoding, including the second word of F000 NNNN
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LdILong(addr) = self {
+            bytes.extend_from_slice(&addr.to_be_bytes());
+        }
+        bytes
+    }
+
+    // the first word of the encoding, see to_bytes for F000 NNNN
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xnn = |op: u16, x: u8, nn: u8| op << 12 | (x as u16 & 0xf) << 8 | nn as u16;
+        let xyn = |op: u16, x: u8, y: u8, n: u8| xnn(op, x, (y & 0xf) << 4 | (n & 0xf));
+        let nnn = |op: u16, addr: u16| op << 12 | (addr & 0xfff);
+
+        match *self {
+            ScrollDown(n) => 0x00c0 | (n as u16 & 0xf),
+            ScrollUp(n) => 0x00d0 | (n as u16 & 0xf),
+            Cls => 0x00e0,
+            Ret => 0x00ee,
+            ScrollRight => 0x00fb,
+            ScrollLeft => 0x00fc,
+            Exit => 0x00fd,
+            Lores => 0x00fe,
+            Hires => 0x00ff,
+            Sys(addr) => nnn(0x0, addr),
+            Jp(addr) => nnn(0x1, addr),
+            Call(addr) => nnn(0x2, addr),
+            Se(x, nn) => xnn(0x3, x, nn),
+            Sne(x, nn) => xnn(0x4, x, nn),
+            SeReg(x, y) => xyn(0x5, x, y, 0x0),
+            SaveRange(x, y) => xyn(0x5, x, y, 0x2),
+            LoadRange(x, y) => xyn(0x5, x, y, 0x3),
+            Ld(x, nn) => xnn(0x6, x, nn),
+            Add(x, nn) => xnn(0x7, x, nn),
+            LdReg(x, y) => xyn(0x8, x, y, 0x0),
+            Or(x, y) => xyn(0x8, x, y, 0x1),
+            And(x, y) => xyn(0x8, x, y, 0x2),
+            Xor(x, y) => xyn(0x8, x, y, 0x3),
+            AddReg(x, y) => xyn(0x8, x, y, 0x4),
+            Sub(x, y) => xyn(0x8, x, y, 0x5),
+            Shr(x, y) => xyn(0x8, x, y, 0x6),
+            Subn(x, y) => xyn(0x8, x, y, 0x7),
+            Shl(x, y) => xyn(0x8, x, y, 0xe),
+            SneReg(x, y) => xyn(0x9, x, y, 0x0),
+            LdI(addr) => nnn(0xa, addr),
+            JpV0(addr) => nnn(0xb, addr),
+            Rnd(x, nn) => xnn(0xc, x, nn),
+            Drw(x, y, n) => xyn(0xd, x, y, n),
+            Skp(x) => xnn(0xe, x, 0x9e),
+            Sknp(x) => xnn(0xe, x, 0xa1),
+            LdILong(_) => 0xf000,
+            Plane(n) => xnn(0xf, n, 0x01),
+            Audio => 0xf002,
+            GetDelay(x) => xnn(0xf, x, 0x07),
+            WaitKey(x) => xnn(0xf, x, 0x0a),
+            SetDelay(x) => xnn(0xf, x, 0x15),
+            SetSound(x) => xnn(0xf, x, 0x18),
+            AddI(x) => xnn(0xf, x, 0x1e),
+            LdFont(x) => xnn(0xf, x, 0x29),
+            LdBigFont(x) => xnn(0xf, x, 0x30),
+            Bcd(x) => xnn(0xf, x, 0x33),
+            Pitch(x) => xnn(0xf, x, 0x3a),
+            StoreRegs(x) => xnn(0xf, x, 0x55),
+            LoadRegs(x) => xnn(0xf, x, 0x65),
+            SaveFlags(x) => xnn(0xf, x, 0x75),
+            LoadFlags(x) => xnn(0xf, x, 0x85),
+        }
+    }
+}
+
+// classic (Cowgod/Chipper style) mnemonics, hex immediates prefixed with #
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Lores => write!(f, "LOW"),
+            Hires => write!(f, "HIGH"),
+            Sys(addr) => write!(f, "SYS #{:03X}", addr),
+            Jp(addr) => write!(f, "JP #{:03X}", addr),
+            Call(addr) => write!(f, "CALL #{:03X}", addr),
+            Se(x, nn) => write!(f, "SE V{:X}, #{:02X}", x, nn),
+            Sne(x, nn) => write!(f, "SNE V{:X}, #{:02X}", x, nn),
+            SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Ld(x, nn) => write!(f, "LD V{:X}, #{:02X}", x, nn),
+            Add(x, nn) => write!(f, "ADD V{:X}, #{:02X}", x, nn),
+            LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(addr) => write!(f, "LD I, #{:03X}", addr),
+            JpV0(addr) => write!(f, "JP V0, #{:03X}", addr),
+            Rnd(x, nn) => write!(f, "RND V{:X}, #{:02X}", x, nn),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdILong(addr) => write!(f, "LD I, LONG #{:04X}", addr),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            GetDelay(x) => write!(f, "LD V{:X}, DT", x),
+            WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            AddI(x) => write!(f, "ADD I, V{:X}", x),
+            LdFont(x) => write!(f, "LD F, V{:X}", x),
+            LdBigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Pitch(x) => write!(f, "LD PITCH, V{:X}", x),
+            StoreRegs(x) => write!(f, "LD [I], V{:X}", x),
+            LoadRegs(x) => write!(f, "LD V{:X}, [I]", x),
+            SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trip() {
+        for opcode in 0..=0xffff {
+            if let Ok(insn) = Instruction::decode(opcode) {
+                assert_eq!(insn.encode(), opcode, "{:04x} decoded to {:?}", opcode, insn);
+                assert_eq!(insn.to_bytes(), opcode.to_be_bytes(), "{:04x}", opcode);
+                assert_eq!(insn.size(), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn f000_needs_the_next_word() {
+        assert_eq!(Instruction::decode(0xf000), Err(DecodeError(0xf000)));
+    }
+
+    #[test]
+    fn long_round_trip() {
+        for next in [0x0000, 0x0300, 0x1234, 0xffff] {
+            let insn = Instruction::decode_long(0xf000, next).unwrap();
+            assert_eq!(insn, Instruction::LdILong(next));
+            assert_eq!(insn.encode(), 0xf000);
+            assert_eq!(insn.size(), 4);
+
+            let [hi, lo] = next.to_be_bytes();
+            assert_eq!(insn.to_bytes(), [0xf0, 0x00, hi, lo]);
+        }
+
+        // anything else ignores the next word
+        assert_eq!(Instruction::decode_long(0x6012, 0xffff), Ok(Instruction::Ld(0, 0x12)));
+    }
+}
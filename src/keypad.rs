@@ -0,0 +1,29 @@
+pub const NUM_KEYS: usize = 16;
+
+// state of the 16 key hexadecimal keypad, 0x0..=0xf
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Keypad {
+    keys: [bool; NUM_KEYS],
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Keypad { keys: [false; NUM_KEYS] }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.keys[(key & 0xf) as usize] = true;
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.keys[(key & 0xf) as usize] = false;
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.keys[(key & 0xf) as usize]
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys = [false; NUM_KEYS];
+    }
+}
@@ -0,0 +1,23 @@
+pub mod assembler;
+pub mod audio;
+pub mod chipper;
+pub mod cpu;
+pub mod dap;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod flags;
+pub mod font;
+pub mod frontend;
+pub mod gdb;
+pub mod instruction;
+pub mod keypad;
+pub mod octo;
+pub mod quirks;
+pub mod record;
+pub mod repl;
+pub mod runner;
+pub mod screenshot;
+pub mod stack;
+pub mod symbols;
+pub mod terminal;
@@ -1,24 +1,437 @@
-pub mod cpu;
-use pretty_hex::*;
+use std::convert::TryFrom;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::cpu::nibbles_to_bytes;
+use rschip8::audio::{self, Audio, Buzzer, WavWriter};
+use rschip8::chipper;
+use rschip8::cpu::{self, CPU};
+use rschip8::dap::{self, DapServer};
+use rschip8::debugger::Debugger;
+use rschip8::disasm::{Disassembly, Syntax};
+use rschip8::flags::FileFlagStorage;
+use rschip8::frontend::{Frontend, Headless};
+use rschip8::gdb::GdbStub;
+use rschip8::octo;
+use rschip8::quirks::QuirkProfile;
+use rschip8::record::{Recorder, VideoFormat};
+use rschip8::repl::Repl;
+use rschip8::runner::{self, Pace};
+use rschip8::screenshot::{Palette, Screenshot};
+use rschip8::stack::{self, StackDepth};
+use rschip8::symbols::{self, SymbolMap};
+use rschip8::terminal::{RenderMode, Terminal};
+
+// how long the headless frontend runs for if not told otherwise, 10 seconds
+const DEFAULT_HEADLESS_FRAMES: u64 = 600;
+
+const USAGE: &str = "\
+usage: rschip8 run <rom> [options]
+       rschip8 debug <rom> [--load-addr, --speed, --quirks, --stack, --flags-dir]
+                           [--gdb <port>]
+       rschip8 dap [<rom>] [--load-addr, --speed, --quirks, --stack, --flags-dir]
+                           [--symbols <file>]
+       rschip8 disasm <rom> [--load-addr, --syntax]
+       rschip8 asm <source> [--output <rom>, --syntax]
+
+options:
+    --load-addr <addr>   where to load the ROM (default 0x200, ETI-660 ROMs use 0x600)
+    --speed <n>          instructions per 60 Hz frame (default 10)
+    --quirks <profile>   vip, chip48, schip or xochip (default vip)
+    --stack <depth>      how many calls deep the stack goes, a number,
+                         unlimited, or vip for 12 mirrored into memory at
+                         0xEA0 (default 12 for vip, 16 otherwise)
+    --frontend <name>    terminal or headless (default terminal)
+    --render <mode>      how the terminal draws pixels, halfblock or braille
+                         (default halfblock)
+    --keymap <keys>      the 16 keyboard keys for keypad keys 0-F in order
+                         (default x123qweasdzc4rfv)
+    --frames <n>         stop after this many frames
+    --screenshot <png>   headless: save the final frame as a PNG instead of
+                         printing it
+    --dump-every <n>     headless: also save every nth frame, numbered
+    --record <file>      record a .gif or .y4m video, - writes Y4M to stdout
+                         (which needs --frontend headless)
+    --record-format <f>  gif or y4m, if the file name doesn't say
+    --scale <n>          screenshot and recording pixel size (default 1)
+    --palette <colours>  screenshot and recording colours, 2-4 hex colours like
+                         000000,ffffff
+    --wav <file>         write the buzzer, or XO-CHIP audio patterns, to a WAV
+                         file
+    --waveform <wave>    square or sine (default square)
+    --tone <hz>          buzzer frequency when there is no pattern (default 440)
+    --volume <percent>   buzzer volume (default 25)
+    --flags-dir <dir>    where to keep SCHIP/XO-CHIP flags between runs
+                         (default $XDG_DATA_HOME/rschip8/flags)
+    --gdb <port>         debug: serve the gdb remote protocol on localhost
+                         instead of the prompt
+    --symbols <file>     dap: symbols for the ROM (default <rom>.sym)
+    --syntax <name>      disasm and asm: classic (Chipper) or octo. disasm
+                         defaults to classic, asm to octo unless the source
+                         ends in .chp or .c8
+    --output <rom>       asm: where to write the ROM (default <source>.ch8),
+                         with symbols next to it in <rom>.sym";
+
+// positional arguments and `--name value` options from the command line
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    // `known` lists the options that may appear, all of which take a value
+    fn parse(args: &[String], known: &[&str]) -> Result<Args, String> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => {
+                    positional.push(arg.clone());
+                    continue;
+                },
+            };
+
+            let (name, value) = match name.split_once('=') {
+                Some((name, value)) => (name, value.to_string()),
+                None => {
+                    let value = iter.next().ok_or(format!("--{} needs a value", name))?;
+                    (name, value.clone())
+                },
+            };
+
+            if !known.contains(&name) {
+                return Err(format!("unknown option --{}", name));
+            }
+            options.insert(name.to_string(), value);
+        }
+
+        Ok(Args { positional, options })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn get_number(&self, name: &str) -> Result<Option<u64>, String> {
+        self.get(name)
+            .map(|value| parse_number(value).ok_or(format!("--{}: invalid number '{}'", name, value)))
+            .transpose()
+    }
+}
+
+// where flags are saved if --flags-dir isn't given
+fn default_flags_dir() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data_home.join("rschip8").join("flags"))
+}
+
+// decimal, or hex with a 0x prefix
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// the CPU with the ROM from the command line loaded and set up how the
+// options ask, shared by run and debug
+fn load(args: &Args) -> Result<CPU, Box<dyn Error>> {
+    let rom_path = match args.positional.as_slice() {
+        [path] => path,
+        _ => return Err(USAGE.into()),
+    };
+
+    let rom = fs::read(rom_path).map_err(|e| format!("{}: {}", rom_path, e))?;
+    let mut cpu = CPU::new();
+
+    let profile = args.get("quirks").map(str::parse::<QuirkProfile>).transpose()?;
+    if let Some(profile) = profile {
+        cpu.set_quirks(profile.quirks());
+        cpu.resize_memory(profile.memory_size());
+        cpu.set_stack_depth(profile.stack_depth());
+    }
+
+    match args.get("stack") {
+        Some("vip") => {
+            cpu.set_stack_depth(StackDepth::Limited(stack::VIP_STACK_DEPTH));
+            cpu.set_memory_mapped_stack(true);
+        },
+        Some("unlimited") => cpu.set_stack_depth(StackDepth::Unlimited),
+        _ => if let Some(depth) = args.get_number("stack")? {
+            cpu.set_stack_depth(StackDepth::Limited(depth as usize));
+        },
+    }
+
+    let load_addr = args.get_number("load-addr")?.unwrap_or(cpu::PROGRAM_START as u64);
+    if load_addr >= cpu.memory_size() as u64 {
+        return Err(format!("load address {:#x} is outside memory", load_addr).into());
+    }
+    let available = cpu.memory_size() - load_addr as usize;
+    if rom.len() > available {
+        return Err(format!(
+            "{}: ROM is {} bytes but only {} bytes are available from {:#x}",
+            rom_path, rom.len(), available, load_addr,
+        ).into());
+    }
+    cpu.load_rom(load_addr as u16, &rom)?;
+
+    // without anywhere to put them flags just live in memory
+    if let Some(dir) = args.get("flags-dir").map(PathBuf::from).or_else(default_flags_dir) {
+        cpu.set_flag_storage(Box::new(FileFlagStorage::for_rom(&dir, &rom)));
+    }
+
+    if let Some(speed) = args.get_number("speed")? {
+        cpu.set_instructions_per_frame(speed as u32);
+    }
+
+    Ok(cpu)
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &[
+        "load-addr", "speed", "quirks", "stack", "frontend", "render", "keymap", "frames", "flags-dir",
+        "screenshot", "dump-every", "scale", "palette", "record", "record-format",
+        "wav", "waveform", "tone", "volume",
+    ])?;
+    let mut cpu = load(&args)?;
+
+    let scale = args.get_number("scale")?.unwrap_or(1).max(1) as usize;
+    let palette: Palette = args.get("palette").map(str::parse).transpose()?.unwrap_or_default();
+    let record = args.get("record").map(PathBuf::from);
+    let record_stdout = record.as_deref() == Some(Path::new("-"));
+    let record = record.map(|path| -> Result<_, Box<dyn Error>> {
+        let format = match args.get("record-format") {
+            Some(format) => format.parse()?,
+            None if record_stdout => VideoFormat::Y4m,
+            None => VideoFormat::from_path(&path)
+                .ok_or_else(|| format!("{}: unknown video format, use --record-format", path.display()))?,
+        };
+        Ok((path, format))
+    }).transpose()?;
+
+    let frames = args.get_number("frames")?;
+    let (mut frontend, pace, frames): (Box<dyn Frontend>, _, _) = match args.get("frontend").unwrap_or("terminal") {
+        "terminal" => {
+            if record_stdout {
+                return Err("can't record to stdout while using the terminal".into());
+            }
+            let mode = args.get("render").map(str::parse).transpose()?.unwrap_or(RenderMode::HalfBlock);
+            let keymap = args.get("keymap").map(str::parse).transpose()?.unwrap_or_default();
+            (Box::new(Terminal::new(mode, keymap)?), Pace::RealTime, frames)
+        },
+        "headless" => {
+            let screenshot = args.get("screenshot").map(|path| -> Result<_, Box<dyn Error>> {
+                let mut screenshot = Screenshot::new(path);
+                screenshot.scale = scale;
+                screenshot.palette = palette;
+                screenshot.every = args.get_number("dump-every")?;
+                Ok(screenshot)
+            }).transpose()?;
+            let mut headless = Headless::new(screenshot);
+            headless.set_print(!record_stdout);
+            (Box::new(headless), Pace::Unthrottled, frames.or(Some(DEFAULT_HEADLESS_FRAMES)))
+        },
+        other => return Err(format!("unknown frontend '{}'", other).into()),
+    };
+
+    if let Some((path, format)) = record {
+        frontend = Box::new(Recorder::create(frontend, &path, format, scale, &palette)?);
+    }
+
+    if let Some(path) = args.get("wav") {
+        let mut buzzer = Buzzer::new(audio::DEFAULT_SAMPLE_RATE);
+        if let Some(waveform) = args.get("waveform") {
+            buzzer.set_waveform(waveform.parse()?);
+        }
+        if let Some(tone) = args.get_number("tone")? {
+            buzzer.set_frequency(tone as f32);
+        }
+        if let Some(volume) = args.get_number("volume")? {
+            buzzer.set_volume(volume as f32 / 100.0);
+        }
+
+        let wav = WavWriter::create(Path::new(path), audio::DEFAULT_SAMPLE_RATE)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        frontend = Box::new(Audio::new(frontend, buzzer, Box::new(wav), audio::DEFAULT_SAMPLE_RATE));
+    }
+
+    runner::run(&mut cpu, frontend.as_mut(), pace, frames)?;
+    Ok(())
+}
+
+fn debug(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["load-addr", "speed", "quirks", "stack", "flags-dir", "gdb"])?;
+    let cpu = load(&args)?;
+
+    if let Some(port) = args.get_number("gdb")? {
+        let port = u16::try_from(port).map_err(|_| format!("--gdb: invalid port {}", port))?;
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        eprintln!("waiting for gdb on {}", listener.local_addr()?);
+
+        let (stream, _) = listener.accept()?;
+        GdbStub::new(Debugger::new(cpu), stream)?.run()?;
+        return Ok(());
+    }
+
+    // ctrl-c stops a continue rather than killing us
+    let interrupted: &'static AtomicBool = Box::leak(Box::new(AtomicBool::new(false)));
+    ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))?;
+
+    let stdin = io::stdin();
+    let mut repl = Repl::new(Debugger::new(cpu), io::stdout(), interrupted);
+    repl.run(stdin.lock())?;
+    Ok(())
+}
+
+fn dap(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["load-addr", "speed", "quirks", "stack", "flags-dir", "symbols"])?;
+    let rx = dap::spawn_reader(BufReader::new(io::stdin()));
+    let mut server = DapServer::new(io::stdout(), rx);
+
+    // with a ROM on the command line the client can attach rather than
+    // launch
+    if let Some(rom_path) = args.positional.first() {
+        let cpu = load(&args)?;
+        let symbols_path = args.get("symbols").map(PathBuf::from)
+            .unwrap_or_else(|| symbols::path_for_rom(Path::new(rom_path)));
+        let symbols = match SymbolMap::load(&symbols_path) {
+            Ok(symbols) => symbols,
+            Err(_) if args.get("symbols").is_none() => SymbolMap::new(),
+            Err(e) => return Err(format!("{}: {}", symbols_path.display(), e).into()),
+        };
+        server.set_target(Debugger::new(cpu), symbols);
+    }
+
+    server.run()?;
+    Ok(())
+}
+
+fn disasm(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["load-addr", "syntax"])?;
+    let rom_path = match args.positional.as_slice() {
+        [path] => path,
+        _ => return Err(USAGE.into()),
+    };
+
+    let rom = fs::read(rom_path).map_err(|e| format!("{}: {}", rom_path, e))?;
+    let load_addr = args.get_number("load-addr")?.unwrap_or(cpu::PROGRAM_START as u64);
+    let load_addr = u16::try_from(load_addr).map_err(|_| format!("--load-addr: invalid address {:#x}", load_addr))?;
+    let syntax = args.get("syntax").map(str::parse::<Syntax>).transpose()?.unwrap_or(Syntax::Classic);
+
+    let stdout = io::stdout();
+    Disassembly::new(&rom, load_addr).write(&mut stdout.lock(), syntax)?;
+    Ok(())
+}
+
+fn asm(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(args, &["output", "syntax"])?;
+    let source_path = match args.positional.as_slice() {
+        [path] => path,
+        _ => return Err(USAGE.into()),
+    };
+
+    let source = fs::read_to_string(source_path).map_err(|e| format!("{}: {}", source_path, e))?;
+    // the debuggers may not run from here, so the source map wants the full
+    // path
+    let full_path = fs::canonicalize(source_path).map_err(|e| format!("{}: {}", source_path, e))?;
+    let full_path = full_path.to_string_lossy();
+    let syntax = match args.get("syntax") {
+        Some(syntax) => syntax.parse()?,
+        None => Syntax::from_path(Path::new(source_path)),
+    };
+    let assembled = match syntax {
+        Syntax::Classic => chipper::assemble(&source, &full_path),
+        Syntax::Octo => octo::assemble(&source, &full_path),
+    };
+    let assembled = assembled.map_err(|e| format!("{}:{}", source_path, e))?;
+    if assembled.origin != cpu::PROGRAM_START {
+        eprintln!("note: the ROM starts at {:#05x}, run it with --load-addr {:#x}", assembled.origin, assembled.origin);
+    }
+
+    let rom_path = args.get("output").map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(source_path).with_extension("ch8"));
+    fs::write(&rom_path, &assembled.rom).map_err(|e| format!("{}: {}", rom_path.display(), e))?;
+    let symbols_path = symbols::path_for_rom(&rom_path);
+    assembled.symbols.save(&symbols_path).map_err(|e| format!("{}: {}", symbols_path.display(), e))?;
+    Ok(())
+}
 
 fn main() {
-    // let mut cpu = cpu::CPU::new();
-    // let font = include_bytes!("rom.bin");
-    // cpu.write_bytes(0, &font.to_vec());
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("run") => run(&args[1..]),
+        Some("debug") => debug(&args[1..]),
+        Some("dap") => dap(&args[1..]),
+        Some("disasm") => disasm(&args[1..]),
+        Some("asm") => asm(&args[1..]),
+        _ => Err(USAGE.into()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Args, String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        Args::parse(&args, &["quirks", "load-addr", "speed", "stack", "flags-dir"])
+    }
+
+    // `rom` written to a temporary file and loaded with `options`
+    fn load_rom(name: &str, rom: &[u8], options: &str) -> Result<CPU, String> {
+        let dir = env::temp_dir().join(format!("rschip8-main-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.ch8");
+        fs::write(&path, rom).unwrap();
+
+        let line = format!("{} --flags-dir {} {}", path.display(), dir.display(), options);
+        let result = load(&args(&line).unwrap()).map_err(|e| e.to_string());
+        fs::remove_dir_all(&dir).unwrap();
+        result
+    }
 
-    let mut cpu = cpu::CPU::new();
-    cpu.go(0x200);
+    #[test]
+    fn options() {
+        let parsed = args("game.ch8 --quirks schip --speed=0x20").unwrap();
+        assert_eq!(parsed.positional, ["game.ch8"]);
+        assert_eq!(parsed.get("quirks"), Some("schip"));
+        assert_eq!(parsed.get_number("speed"), Ok(Some(32)));
+        assert_eq!(parsed.get_number("load-addr"), Ok(None));
 
-    let instructions: Vec<u8> = [
-        0xa1, 0x23,     // set i to 0x123
-    ].to_vec();
-    cpu.write_bytes(0x200, &instructions);
+        assert_eq!(args("--speed fast").unwrap().get_number("speed"), Err("--speed: invalid number 'fast'".to_string()));
+        assert_eq!(args("game.ch8 --speed").err(), Some("--speed needs a value".to_string()));
+        assert_eq!(args("--colour red").err(), Some("unknown option --colour".to_string()));
+    }
 
-    cpu.clock(1);
+    #[test]
+    fn loading() {
+        let cpu = load_rom("options", &[0x12, 0x00], "--quirks xochip --speed 50 --load-addr 0x300").unwrap();
+        assert_eq!(cpu.memory_size(), 0x10000);
+        assert_eq!(cpu.get_instructions_per_frame(), 50);
+        assert_eq!(cpu.read_word(0x300), Ok(0x1200));
 
-    println!("PC ended on {}", cpu.get_pc());
-    // println!("CPU: {:#x?}", cpu.regs);
-    // println!("{}", pretty_hex(&cpu.read_memory()));
+        let error = load_rom("outside", &[0x12, 0x00], "--load-addr 0x1000").unwrap_err();
+        assert_eq!(error, "load address 0x1000 is outside memory");
+        let error = load_rom("too-big", &[0; 0xe01], "").unwrap_err();
+        assert!(error.ends_with("ROM is 3585 bytes but only 3584 bytes are available from 0x200"), "{}", error);
+        let error = load_rom("quirks", &[], "--quirks pdp11").unwrap_err();
+        assert!(error.contains("pdp11"), "{}", error);
+    }
 }
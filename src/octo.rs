@@ -0,0 +1,821 @@
+use std::collections::HashMap;
+use std::f64::consts;
+use std::iter;
+
+use crate::assembler::{AsmError, Assembled, Image, Patch, Position};
+use crate::instruction::Instruction;
+
+// where Octo programs start, with a jump to main unless main comes first
+const ORIGIN: u16 = 0x200;
+
+// how many macros one program can expand, so a macro that uses itself fails
+// rather than running forever
+const MAX_EXPANSIONS: u32 = 100_000;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    position: Position,
+}
+
+// Octo is whitespace separated words with # comments to the end of a line
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (n, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut start = None;
+        for (i, c) in line.char_indices().chain(iter::once((line.len(), ' '))) {
+            match (c.is_whitespace(), start) {
+                (true, Some(s)) => {
+                    let column = line[..s].chars().count() as u32 + 1;
+                    let position = Position { line: n as u32 + 1, column };
+                    tokens.push(Token { text: line[s..i].to_string(), position });
+                    start = None;
+                },
+                (false, None) => start = Some(i),
+                _ => {},
+            }
+        }
+    }
+
+    tokens
+}
+
+// a number as written, decimal, 0x hex or 0b binary with an optional minus
+fn literal(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+// the skip that runs the next instruction in the opposite case
+fn negate(skip: Instruction) -> Instruction {
+    use Instruction::*;
+
+    match skip {
+        Se(x, nn) => Sne(x, nn),
+        Sne(x, nn) => Se(x, nn),
+        SeReg(x, y) => SneReg(x, y),
+        SneReg(x, y) => SeReg(x, y),
+        Skp(x) => Sknp(x),
+        Sknp(x) => Skp(x),
+        _ => unreachable!("{} isn't a skip", skip),
+    }
+}
+
+// the result of `op`, which has to be a usable number
+fn finite(value: f64, op: &Token) -> Result<f64, AsmError> {
+    if !value.is_finite() {
+        return Err(op.position.error(format!("'{}' doesn't give a finite number", op.text)));
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+// an if or loop that hasn't been closed yet
+#[derive(Debug)]
+enum Block {
+    // `jump` is the jump to patch to the else or end
+    If { jump: u16, has_else: bool, position: Position },
+    // `breaks` are the jumps out from whiles
+    Loop { start: u16, breaks: Vec<u16>, position: Position },
+}
+
+struct Assembler {
+    // the rest of the program, backwards so the next token is at the end
+    tokens: Vec<Token>,
+    last: Position,
+    image: Image,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    blocks: Vec<Block>,
+    expansions: u32,
+    // whether the jump to main at the start is still there
+    main_jump: bool,
+}
+
+// assemble Octo source. `file` is what the source map calls it
+pub fn assemble(source: &str, file: &str) -> Result<Assembled, AsmError> {
+    let mut tokens = tokenize(source);
+    tokens.reverse();
+
+    let mut image = Image::new(file, ORIGIN);
+    let start = Position { line: 1, column: 1 };
+    image.reference(ORIGIN, Patch::Addr12, "main", start);
+    image.emit_byte(0x10, start)?;
+    image.emit_byte(0x00, start)?;
+
+    let mut assembler = Assembler {
+        tokens,
+        last: start,
+        image,
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        blocks: Vec::new(),
+        expansions: 0,
+        main_jump: true,
+    };
+
+    while !assembler.tokens.is_empty() {
+        assembler.statement()?;
+    }
+
+    match assembler.blocks.pop() {
+        Some(Block::If { position, .. }) => return Err(position.error("if without an end")),
+        Some(Block::Loop { position, .. }) => return Err(position.error("loop without an again")),
+        None => {},
+    }
+    if assembler.image.label("main").is_none() {
+        return Err(start.error("no ': main' label to start from"));
+    }
+
+    assembler.image.finish()
+}
+
+impl Assembler {
+    fn next(&mut self) -> Result<Token, AsmError> {
+        let token = self.tokens.pop().ok_or_else(|| self.last.error("unexpected end of file"))?;
+        self.last = token.position;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.position.error(format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    // a name for a label, constant, alias or macro
+    fn name(&mut self) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if literal(&token.text).is_some() || register(&token.text).is_some() {
+            return Err(token.position.error(format!("'{}' can't be used as a name", token.text)));
+        }
+        Ok(token)
+    }
+
+    // a literal or a constant
+    fn number(&self, text: &str) -> Option<f64> {
+        literal(text).map(|value| value as f64).or_else(|| self.consts.get(text).copied())
+    }
+
+    fn value(&mut self) -> Result<(i64, Token), AsmError> {
+        let token = self.next()?;
+        match self.number(&token.text) {
+            Some(value) => Ok((value.floor() as i64, token)),
+            None => Err(token.position.error(format!("expected a number, found '{}'", token.text))),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let (value, token) = self.value()?;
+        if !(-128..=255).contains(&value) {
+            return Err(token.position.error(format!("{} doesn't fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        let (value, token) = self.value()?;
+        if !(0..=15).contains(&value) {
+            return Err(token.position.error(format!("{} doesn't fit in a nibble", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.as_register(&token.text)
+            .ok_or_else(|| token.position.error(format!("expected a register, found '{}'", token.text)))
+    }
+
+    fn as_register(&self, text: &str) -> Option<u8> {
+        register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    // `insn` with the address that comes next filled in, a number or a label
+    // that may not be defined yet. `max` is the biggest address that fits
+    fn emit_addressed(
+        &mut self,
+        insn: impl Fn(u16) -> Instruction,
+        max: u16,
+        position: Position,
+    ) -> Result<(), AsmError> {
+        let token = self.next()?;
+        match self.number(&token.text) {
+            Some(value) => {
+                let addr = value.floor() as i64;
+                if !(0..=max as i64).contains(&addr) {
+                    return Err(token.position.error(format!("address {:#x} is out of range", addr)));
+                }
+                self.image.emit(insn(addr as u16), position)
+            },
+            None => self.image.emit_reference(insn(0), &token.text, token.position),
+        }
+    }
+
+    // the tokens between { and the } that goes with it
+    fn braced(&mut self) -> Result<Vec<Token>, AsmError> {
+        let open = self.expect("{")?;
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next().map_err(|_| open.position.error("{ without a }"))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                },
+                _ => {},
+            }
+            body.push(token);
+        }
+    }
+
+    // a number, or a { } expression like :calc takes
+    fn calc_or_value(&mut self) -> Result<(i64, Position), AsmError> {
+        if self.peek() == Some("{") {
+            let position = self.tokens.last().unwrap().position;
+            let body = self.braced()?;
+            let value = self.calc(&body, position)?;
+            return Ok((value.floor() as i64, position));
+        }
+        let (value, token) = self.value()?;
+        Ok((value, token.position))
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        use Instruction::*;
+
+        let token = self.next()?;
+        let at = token.position;
+        let insn = match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if name.text == "main" && self.main_jump && self.image.written_bytes() == 2 {
+                    // main is first, so there's no need to jump to it
+                    self.image.erase(ORIGIN, ORIGIN + 2);
+                    if self.image.here() == ORIGIN + 2 {
+                        self.image.set_here(ORIGIN);
+                    }
+                    self.main_jump = false;
+                }
+                return self.image.define_label(&name.text, name.position);
+            },
+            ":const" => {
+                let name = self.name()?;
+                let (value, _) = self.value()?;
+                self.consts.insert(name.text, value as f64);
+                return Ok(());
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x);
+                return Ok(());
+            },
+            ":calc" => {
+                let name = self.name()?;
+                let open = self.tokens.last().map_or(self.last, |token| token.position);
+                let body = self.braced()?;
+                let value = self.calc(&body, open)?;
+                self.consts.insert(name.text, value);
+                return Ok(());
+            },
+            ":macro" => {
+                let name = self.name()?;
+                let mut args = Vec::new();
+                while self.peek().is_some_and(|text| text != "{") {
+                    args.push(self.name()?.text);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name.text, Macro { args, body });
+                return Ok(());
+            },
+            ":byte" => {
+                let (value, position) = self.calc_or_value()?;
+                if !(-128..=255).contains(&value) {
+                    return Err(position.error(format!("{} doesn't fit in a byte", value)));
+                }
+                return self.image.emit_byte(value as u8, at);
+            },
+            ":org" => {
+                let (addr, position) = self.calc_or_value()?;
+                if !(0..=0xffff).contains(&addr) {
+                    return Err(position.error(format!("address {:#x} is out of range", addr)));
+                }
+                self.image.set_here(addr as u16);
+                return Ok(());
+            },
+            ":call" => return self.emit_addressed(Call, 0xfff, at),
+            ":proto" => {
+                self.name()?;
+                return Ok(());
+            },
+            "clear" => Cls,
+            "return" | ";" => Ret,
+            "exit" => Exit,
+            "lores" => Lores,
+            "hires" => Hires,
+            "scroll-right" => ScrollRight,
+            "scroll-left" => ScrollLeft,
+            "scroll-down" => ScrollDown(self.nibble()?),
+            "scroll-up" => ScrollUp(self.nibble()?),
+            "audio" => Audio,
+            "plane" => Plane(self.nibble()?),
+            "bcd" => Bcd(self.register()?),
+            "saveflags" => SaveFlags(self.register()?),
+            "loadflags" => LoadFlags(self.register()?),
+            "save" | "load" => {
+                let x = self.register()?;
+                let save = token.text == "save";
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if save { SaveRange(x, y) } else { LoadRange(x, y) }
+                } else if save {
+                    StoreRegs(x)
+                } else {
+                    LoadRegs(x)
+                }
+            },
+            "sprite" => Drw(self.register()?, self.register()?, self.nibble()?),
+            "jump" => return self.emit_addressed(Jp, 0xfff, at),
+            "jump0" => return self.emit_addressed(JpV0, 0xfff, at),
+            "native" => return self.emit_addressed(Sys, 0xfff, at),
+            "i" => {
+                let op = self.next()?;
+                match op.text.as_str() {
+                    ":=" => match self.peek() {
+                        Some("hex") => {
+                            self.next()?;
+                            LdFont(self.register()?)
+                        },
+                        Some("bighex") => {
+                            self.next()?;
+                            LdBigFont(self.register()?)
+                        },
+                        Some("long") => {
+                            self.next()?;
+                            return self.emit_addressed(LdILong, 0xffff, at);
+                        },
+                        _ => return self.emit_addressed(LdI, 0xfff, at),
+                    },
+                    "+=" => AddI(self.register()?),
+                    _ => return Err(op.position.error(format!("unknown operator 'i {}'", op.text))),
+                }
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                match token.text.as_str() {
+                    "delay" => SetDelay(x),
+                    "buzzer" => SetSound(x),
+                    _ => Pitch(x),
+                }
+            },
+            "if" => return self.conditional(at),
+            "else" => {
+                let jump = self.image.here();
+                let previous = match self.blocks.last_mut() {
+                    Some(Block::If { jump: previous, has_else: has_else @ false, .. }) => {
+                        *has_else = true;
+                        std::mem::replace(previous, jump)
+                    },
+                    _ => return Err(at.error("else without an if ... begin")),
+                };
+                self.image.emit(Jp(0), at)?;
+                let here = self.image.here();
+                self.image.patch(previous, Patch::Addr12, here);
+                return Ok(());
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let here = self.image.here();
+                    self.image.patch(jump, Patch::Addr12, here);
+                    return Ok(());
+                },
+                _ => return Err(at.error("end without an if ... begin")),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop { start: self.image.here(), breaks: Vec::new(), position: at });
+                return Ok(());
+            },
+            "while" => {
+                let skip = self.condition()?;
+                self.image.emit(skip, at)?;
+                let jump = self.image.here();
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return Err(at.error("while outside a loop")),
+                }
+                Jp(0)
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.image.emit(Jp(start), at)?;
+                    let here = self.image.here();
+                    breaks.into_iter().for_each(|jump| self.image.patch(jump, Patch::Addr12, here));
+                    return Ok(());
+                },
+                _ => return Err(at.error("again without a loop")),
+            },
+            text => {
+                if let Some(x) = self.as_register(text) {
+                    self.assignment(x)?
+                } else if let Some(value) = self.number(text) {
+                    let value = value.floor() as i64;
+                    if !(-128..=255).contains(&value) {
+                        return Err(at.error(format!("{} doesn't fit in a byte", value)));
+                    }
+                    return self.image.emit_byte(value as u8, at);
+                } else if self.macros.contains_key(text) {
+                    return self.expand(&token);
+                } else if text.starts_with(':') {
+                    return Err(at.error(format!("unknown directive '{}'", text)));
+                } else {
+                    // a bare name calls that subroutine
+                    return self.image.emit_reference(Call(0), text, at);
+                }
+            },
+        };
+
+        self.image.emit(insn, at)
+    }
+
+    // vx followed by an operator
+    fn assignment(&mut self, x: u8) -> Result<Instruction, AsmError> {
+        use Instruction::*;
+
+        let op = self.next()?;
+        let rhs = self.peek().and_then(|text| self.as_register(text));
+        let insn = match (op.text.as_str(), rhs) {
+            (":=", Some(y)) => LdReg(x, y),
+            (":=", None) => match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    return Ok(Rnd(x, self.byte()?));
+                },
+                Some("key") => WaitKey(x),
+                Some("delay") => GetDelay(x),
+                _ => return Ok(Ld(x, self.byte()?)),
+            },
+            ("+=", Some(y)) => AddReg(x, y),
+            ("+=", None) => return Ok(Add(x, self.byte()?)),
+            ("-=", Some(y)) => Sub(x, y),
+            ("-=", None) => return Ok(Add(x, self.byte()?.wrapping_neg())),
+            ("|=", Some(y)) => Or(x, y),
+            ("&=", Some(y)) => And(x, y),
+            ("^=", Some(y)) => Xor(x, y),
+            ("=-", Some(y)) => Subn(x, y),
+            (">>=", Some(y)) => Shr(x, y),
+            ("<<=", Some(y)) => Shl(x, y),
+            (_, None) if ["|=", "&=", "^=", "=-", ">>=", "<<="].contains(&op.text.as_str()) => {
+                let token = self.next()?;
+                return Err(token.position.error(format!("expected a register, found '{}'", token.text)));
+            },
+            _ => return Err(op.position.error(format!("unknown operator '{}'", op.text))),
+        };
+
+        self.next()?;
+        Ok(insn)
+    }
+
+    // the condition after if or while. anything it needs to work it out is
+    // emitted, and what's left is the skip taken when it's true
+    fn condition(&mut self) -> Result<Instruction, AsmError> {
+        use Instruction::*;
+
+        let x = self.register()?;
+        let op = self.next()?;
+        let (skip, y) = match op.text.as_str() {
+            "key" => return Ok(Skp(x)),
+            "-key" => return Ok(Sknp(x)),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let rhs = self.peek().and_then(|text| self.as_register(text));
+                match rhs {
+                    Some(y) => {
+                        self.next()?;
+                        (op.text.clone(), Ok(y))
+                    },
+                    None => (op.text.clone(), Err(self.byte()?)),
+                }
+            },
+            _ => return Err(op.position.error(format!("unknown comparison '{}'", op.text))),
+        };
+
+        let at = op.position;
+        match (skip.as_str(), y) {
+            ("==", Ok(y)) => Ok(SeReg(x, y)),
+            ("==", Err(nn)) => Ok(Se(x, nn)),
+            ("!=", Ok(y)) => Ok(SneReg(x, y)),
+            ("!=", Err(nn)) => Ok(Sne(x, nn)),
+            // the rest subtract into vf, which ends up 0 when there's a
+            // borrow. < and >= want x - y, > and <= want y - x
+            (op, y) => {
+                let x_minus_y = op == "<" || op == ">=";
+                match (x_minus_y, y) {
+                    (true, Ok(y)) => {
+                        self.image.emit(LdReg(0xf, x), at)?;
+                        self.image.emit(Sub(0xf, y), at)?;
+                    },
+                    (true, Err(nn)) => {
+                        self.image.emit(Ld(0xf, nn), at)?;
+                        self.image.emit(Subn(0xf, x), at)?;
+                    },
+                    (false, Ok(y)) => {
+                        self.image.emit(LdReg(0xf, y), at)?;
+                        self.image.emit(Sub(0xf, x), at)?;
+                    },
+                    (false, Err(nn)) => {
+                        self.image.emit(Ld(0xf, nn), at)?;
+                        self.image.emit(Sub(0xf, x), at)?;
+                    },
+                }
+                // < and > are true on a borrow
+                Ok(if op == "<" || op == ">" { Se(0xf, 0) } else { Sne(0xf, 0) })
+            },
+        }
+    }
+
+    fn conditional(&mut self, at: Position) -> Result<(), AsmError> {
+        let skip = self.condition()?;
+        let then = self.next()?;
+        match then.text.as_str() {
+            // skip the next instruction unless it's true
+            "then" => self.image.emit(negate(skip), at),
+            // skip the jump past the block when it's true
+            "begin" => {
+                self.image.emit(skip, at)?;
+                self.blocks.push(Block::If { jump: self.image.here(), has_else: false, position: at });
+                self.image.emit(Instruction::Jp(0), at)
+            },
+            _ => Err(then.position.error(format!("expected 'then' or 'begin', found '{}'", then.text))),
+        }
+    }
+
+    fn expand(&mut self, name: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(name.position.error("too many macro expansions, does a macro use itself?"));
+        }
+
+        let definition = self.macros[&name.text].clone();
+        let mut args = HashMap::new();
+        for arg in &definition.args {
+            let value = self.next()
+                .map_err(|_| name.position.error(format!("macro '{}' needs {} arguments", name.text, definition.args.len())))?;
+            args.insert(arg.as_str(), value.text);
+        }
+
+        for token in definition.body.iter().rev() {
+            let text = args.get(token.text.as_str()).cloned().unwrap_or_else(|| token.text.clone());
+            self.tokens.push(Token { text, position: token.position });
+        }
+        Ok(())
+    }
+
+    // a :calc expression. there's no precedence, everything is worked out
+    // right to left unless there are brackets, so 2 * 3 + 1 is 8
+    fn calc(&self, tokens: &[Token], open: Position) -> Result<f64, AsmError> {
+        let mut i = 0;
+        let value = self.expression(tokens, &mut i, open)?;
+        match tokens.get(i) {
+            Some(token) => Err(token.position.error(format!("unexpected '{}' in expression", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    fn expression(&self, tokens: &[Token], i: &mut usize, open: Position) -> Result<f64, AsmError> {
+        let left = self.term(tokens, i, open)?;
+        let op = match tokens.get(*i) {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok(left),
+        };
+        *i += 1;
+        let right = self.expression(tokens, i, open)?;
+
+        if (op.text == "/" || op.text == "%") && right == 0.0 {
+            return Err(op.position.error("division by zero"));
+        }
+
+        let int = |value: f64| value.floor() as i64;
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+        let value = match op.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (int(left) & int(right)) as f64,
+            "|" => (int(left) | int(right)) as f64,
+            "^" => (int(left) ^ int(right)) as f64,
+            "<<" => int(left).checked_shl(int(right) as u32).unwrap_or(0) as f64,
+            ">>" => int(left).checked_shr(int(right) as u32).unwrap_or(0) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => truth(left < right),
+            ">" => truth(left > right),
+            "<=" => truth(left <= right),
+            ">=" => truth(left >= right),
+            "==" => truth(left == right),
+            "!=" => truth(left != right),
+            _ => return Err(op.position.error(format!("unknown operator '{}'", op.text))),
+        };
+        finite(value, op)
+    }
+
+    fn term(&self, tokens: &[Token], i: &mut usize, open: Position) -> Result<f64, AsmError> {
+        let token = tokens.get(*i).ok_or_else(|| open.error("expression is missing a value"))?;
+        *i += 1;
+
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|value| -value),
+            "~" => Some(|value| !(value.floor() as i64) as f64),
+            "!" => Some(|value| if value == 0.0 { 1.0 } else { 0.0 }),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "floor" => Some(f64::floor),
+            "ceil" => Some(f64::ceil),
+            "sign" => Some(f64::signum),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return finite(unary(self.term(tokens, i, open)?), token);
+        }
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, i, open)?;
+                match tokens.get(*i) {
+                    Some(close) if close.text == ")" => {
+                        *i += 1;
+                        Ok(value)
+                    },
+                    _ => Err(token.position.error("( without a )")),
+                }
+            },
+            // the byte assembled so far at an address
+            "@" => {
+                let addr = self.term(tokens, i, open)?.floor() as i64;
+                Ok(self.image.byte(addr as u16) as f64)
+            },
+            "HERE" => Ok(self.image.here() as f64),
+            "PI" => Ok(consts::PI),
+            "E" => Ok(consts::E),
+            // labels can be used once they're defined
+            text => self.number(text)
+                .or_else(|| self.image.label(text).map(f64::from))
+                .ok_or_else(|| token.position.error(format!("unknown value '{}'", text))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::testing::TestAssembler;
+    use crate::disasm::Syntax;
+
+    const OCTO: TestAssembler = TestAssembler(assemble);
+
+    #[test]
+    fn main_first_needs_no_jump() {
+        assert_eq!(OCTO.rom(": main clear"), [0x00, 0xe0]);
+        assert_eq!(OCTO.rom(": sub return : main sub"), [0x12, 0x04, 0x00, 0xee, 0x22, 0x02]);
+        assert_eq!(OCTO.error("clear"), AsmError::new(1, 1, "no ': main' label to start from"));
+    }
+
+    #[test]
+    fn calc_is_right_to_left() {
+        let source = ": main :calc x { 2 * 3 + 1 } :calc y { ( 2 * 3 ) + 1 } :byte x :byte y";
+        assert_eq!(OCTO.rom(source), [8, 7]);
+        assert_eq!(OCTO.rom(":const A 4 : main :calc b { A << 1 } :byte { b - 1 }"), [7]);
+        assert_eq!(OCTO.error(": main\n:calc x { 1 / 0 }"), AsmError::new(2, 13, "division by zero"));
+    }
+
+    #[test]
+    fn const_and_alias() {
+        assert_eq!(OCTO.rom(":const SPEED 3 :alias x v4 : main x := SPEED x += -1"), [0x64, 0x03, 0x74, 0xff]);
+    }
+
+    #[test]
+    fn macros() {
+        let source = ":macro twice reg { reg += 1 reg += 1 } : main twice v3 twice v5";
+        assert_eq!(OCTO.rom(source), [0x73, 0x01, 0x73, 0x01, 0x75, 0x01, 0x75, 0x01]);
+        assert_eq!(OCTO.error(":macro m { m } : main m").message, "too many macro expansions, does a macro use itself?");
+    }
+
+    #[test]
+    fn org() {
+        assert_eq!(OCTO.rom(": main clear :org 0x206 0xaa"), [0x00, 0xe0, 0, 0, 0, 0, 0xaa]);
+
+        let assembled = OCTO.assemble(":org 0x600 : main clear");
+        assert_eq!(assembled.origin, 0x600);
+        assert_eq!(assembled.rom, [0x00, 0xe0]);
+    }
+
+    #[test]
+    fn if_then() {
+        assert_eq!(OCTO.rom(": main if v0 == 1 then v1 := 2"), [0x40, 0x01, 0x61, 0x02]);
+        assert_eq!(OCTO.rom(": main if v0 key then v1 := 2"), [0xe0, 0xa1, 0x61, 0x02]);
+        assert_eq!(
+            OCTO.rom(": main if v1 < v2 then v3 := 1"),
+            [0x8f, 0x10, 0x8f, 0x25, 0x4f, 0x00, 0x63, 0x01],
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        assert_eq!(
+            OCTO.rom(": main if v0 == 1 begin v1 := 2 else v1 := 3 end"),
+            [0x30, 0x01, 0x12, 0x08, 0x61, 0x02, 0x12, 0x0a, 0x61, 0x03],
+        );
+        assert_eq!(OCTO.error(": main\n  if v0 == 1 begin"), AsmError::new(2, 3, "if without an end"));
+        assert_eq!(OCTO.error(": main else"), AsmError::new(1, 8, "else without an if ... begin"));
+    }
+
+    #[test]
+    fn loop_while() {
+        assert_eq!(
+            OCTO.rom(": main loop v0 += 1 while v0 != 5 again"),
+            [0x70, 0x01, 0x40, 0x05, 0x12, 0x08, 0x12, 0x00],
+        );
+        assert_eq!(OCTO.error(": main while v0 == 1"), AsmError::new(1, 8, "while outside a loop"));
+    }
+
+    #[test]
+    fn forward_references() {
+        assert_eq!(
+            OCTO.rom(": main jump end i := data : end loop again : data 0x12"),
+            [0x12, 0x04, 0xa2, 0x06, 0x12, 0x04, 0x12],
+        );
+        assert_eq!(OCTO.rom(": main i := long data : data 0x34"), [0xf0, 0x00, 0x02, 0x04, 0x34]);
+        assert_eq!(OCTO.error(": main\n  jump nowhere"), AsmError::new(2, 8, "undefined label 'nowhere'"));
+    }
+
+    #[test]
+    fn addresses_out_of_reach() {
+        let source = ": main\n  jump far\n  i := long far\n:org 0x1000\n: far\n  0";
+        assert_eq!(
+            OCTO.error(source),
+            AsmError::new(2, 8, "label 'far' at 0x1000 is out of reach of a 12 bit address"),
+        );
+        assert_eq!(OCTO.rom(": main i := long far :org 0x1000 : far 0").len(), 0x1000 - 0x200 + 1);
+    }
+
+    #[test]
+    fn symbols() {
+        let assembled = OCTO.assemble(": main\n  clear\n: sub\n  return");
+        assert_eq!(assembled.symbols.label("main"), Some(0x200));
+        assert_eq!(assembled.symbols.label("sub"), Some(0x202));
+        assert_eq!(assembled.symbols.line_for_address(0x202).map(|entry| entry.line), Some(4));
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        OCTO.round_trip(Syntax::Octo);
+    }
+}
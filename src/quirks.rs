@@ -0,0 +1,168 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cpu::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+use crate::stack::{StackDepth, SCHIP_STACK_DEPTH, VIP_STACK_DEPTH};
+
+// the behaviours that differ between CHIP-8 implementations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY and store the result in VX, rather than shifting VX
+    pub shift_uses_vy: bool,
+    // FX55/FX65 leave I pointing just past the last register
+    pub load_store_increments_i: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    // BNNN is really BXNN, jumping to XNN + VX rather than NNN + V0
+    pub jump_uses_vx: bool,
+    // sprites are cut off at the edges of the screen rather than wrapping
+    pub clip_sprites: bool,
+    // DXYN waits for the start of the next frame, so at most one sprite is
+    // drawn per frame
+    pub display_wait: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::vip()
+    }
+}
+
+impl Quirks {
+    // the original interpreter on the COSMAC VIP
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: true,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            logic_resets_vf: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1, which inherited most of CHIP-48's behaviour
+    pub fn schip() -> Self {
+        Self::chip48()
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: false,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirkProfile {
+    Vip,
+    Chip48,
+    Schip,
+    XoChip,
+}
+
+impl QuirkProfile {
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            QuirkProfile::Vip => Quirks::vip(),
+            QuirkProfile::Chip48 => Quirks::chip48(),
+            QuirkProfile::Schip => Quirks::schip(),
+            QuirkProfile::XoChip => Quirks::xochip(),
+        }
+    }
+
+    // XO-CHIP programs get the whole 64k
+    pub fn memory_size(&self) -> usize {
+        match self {
+            QuirkProfile::XoChip => XO_CHIP_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        }
+    }
+
+    // the HP-48 interpreters and everything after them allow 16 calls deep
+    pub fn stack_depth(&self) -> StackDepth {
+        match self {
+            QuirkProfile::Vip => StackDepth::Limited(VIP_STACK_DEPTH),
+            _ => StackDepth::Limited(SCHIP_STACK_DEPTH),
+        }
+    }
+}
+
+impl fmt::Display for QuirkProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            QuirkProfile::Vip => "vip",
+            QuirkProfile::Chip48 => "chip48",
+            QuirkProfile::Schip => "schip",
+            QuirkProfile::XoChip => "xochip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for QuirkProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "chip8" => Ok(QuirkProfile::Vip),
+            "chip48" => Ok(QuirkProfile::Chip48),
+            "schip" | "superchip" => Ok(QuirkProfile::Schip),
+            "xochip" | "xo-chip" => Ok(QuirkProfile::XoChip),
+            _ => Err(format!("unknown quirk profile '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: [QuirkProfile; 4] = [QuirkProfile::Vip, QuirkProfile::Chip48, QuirkProfile::Schip, QuirkProfile::XoChip];
+
+    #[test]
+    fn names() {
+        for profile in PROFILES {
+            assert_eq!(profile.to_string().parse(), Ok(profile));
+        }
+        assert_eq!("CHIP8".parse(), Ok(QuirkProfile::Vip));
+        assert_eq!("superchip".parse(), Ok(QuirkProfile::Schip));
+        assert_eq!("XO-CHIP".parse(), Ok(QuirkProfile::XoChip));
+        assert_eq!("eti660".parse::<QuirkProfile>(), Err("unknown quirk profile 'eti660'".to_string()));
+    }
+
+    #[test]
+    fn profiles() {
+        assert_eq!(Quirks::default(), QuirkProfile::Vip.quirks());
+        assert_eq!(QuirkProfile::Schip.quirks(), QuirkProfile::Chip48.quirks());
+        assert_ne!(QuirkProfile::XoChip.quirks(), QuirkProfile::Vip.quirks());
+
+        let memory: Vec<usize> = PROFILES.iter().map(QuirkProfile::memory_size).collect();
+        assert_eq!(memory, [MEMORY_SIZE, MEMORY_SIZE, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE]);
+        let depths: Vec<StackDepth> = PROFILES.iter().map(QuirkProfile::stack_depth).collect();
+        assert_eq!(depths, [
+            StackDepth::Limited(12),
+            StackDepth::Limited(16),
+            StackDepth::Limited(16),
+            StackDepth::Limited(16),
+        ]);
+    }
+}
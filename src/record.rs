@@ -0,0 +1,306 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cpu::{self, CPU};
+use crate::display::{Display, HIRES_HEIGHT, HIRES_WIDTH};
+use crate::frontend::Frontend;
+use crate::screenshot::Palette;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    // deduplicated frames, small enough to attach to a bug report
+    Gif,
+    // every frame uncompressed, for feeding to ffmpeg and friends
+    Y4m,
+}
+
+impl VideoFormat {
+    // guess the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.to_ascii_lowercase().parse().ok()
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(VideoFormat::Gif),
+            "y4m" => Ok(VideoFormat::Y4m),
+            _ => Err(format!("unknown video format '{}'", s)),
+        }
+    }
+}
+
+// videos are always the size of the hires screen (times the scale), lores
+// pixels are doubled so that switching modes doesn't change the frame size.
+// returns a colour index for each pixel
+fn canvas(display: &Display, scale: usize) -> Vec<u8> {
+    let (width, height) = (HIRES_WIDTH * scale, HIRES_HEIGHT * scale);
+    let (sx, sy) = (width / display.width(), height / display.height());
+    let mut data = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            data.push(display.pixel(x / sx, y / sy));
+        }
+    }
+
+    data
+}
+
+// somewhere to put frames, given as colour indices at the canvas size
+pub trait VideoWriter {
+    fn frame(&mut self, canvas: &[u8]) -> io::Result<()>;
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+pub struct GifWriter<W: Write> {
+    encoder: Option<gif::Encoder<W>>,
+    width: u16,
+    height: u16,
+    // the frame waiting to find out how long it stays on screen, and the
+    // frame number it appeared on
+    pending: Option<(Vec<u8>, u64)>,
+    frames: u64,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn new(out: W, scale: usize, palette: &Palette) -> io::Result<Self> {
+        let (width, height) = ((HIRES_WIDTH * scale) as u16, (HIRES_HEIGHT * scale) as u16);
+        let colours: Vec<u8> = palette.colours.iter().flatten().copied().collect();
+
+        let mut encoder = gif::Encoder::new(out, width, height, &colours).map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+
+        Ok(GifWriter { encoder: Some(encoder), width, height, pending: None, frames: 0 })
+    }
+
+    // GIF delays are in hundredths of a second, which 60 Hz doesn't divide
+    // into, so each frame's delay is worked out from where it starts and ends
+    // to stop the rounding error building up
+    fn centiseconds(frame: u64) -> u64 {
+        (frame * 100 + cpu::TIMER_HZ as u64 / 2) / cpu::TIMER_HZ as u64
+    }
+
+    fn flush_pending(&mut self) -> io::Result<()> {
+        let (buffer, start) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let encoder = match &mut self.encoder {
+            Some(encoder) => encoder,
+            None => return Ok(()),
+        };
+
+        let delay = Self::centiseconds(self.frames) - Self::centiseconds(start);
+        let frame = gif::Frame {
+            width: self.width,
+            height: self.height,
+            delay: delay.min(u16::MAX as u64) as u16,
+            buffer: Cow::Owned(buffer),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+}
+
+impl<W: Write> VideoWriter for GifWriter<W> {
+    fn frame(&mut self, canvas: &[u8]) -> io::Result<()> {
+        // an unchanged frame just makes the previous one last longer
+        let changed = self.pending.as_ref().is_none_or(|(pending, _)| pending.as_slice() != canvas);
+        if changed {
+            self.flush_pending()?;
+            self.pending = Some((canvas.to_vec(), self.frames));
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush_pending()?;
+        match self.encoder.take() {
+            Some(encoder) => encoder.into_inner()?.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    // Y, Cb and Cr for each palette colour
+    colours: [[u8; 3]; 4],
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut out: W, scale: usize, palette: &Palette) -> io::Result<Self> {
+        writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", HIRES_WIDTH * scale, HIRES_HEIGHT * scale, cpu::TIMER_HZ)?;
+        Ok(Y4mWriter { out, colours: palette.colours.map(ycbcr) })
+    }
+}
+
+// BT.601 studio range, which is what players assume for Y4M
+fn ycbcr([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let cb = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let cr = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+impl<W: Write> VideoWriter for Y4mWriter<W> {
+    fn frame(&mut self, canvas: &[u8]) -> io::Result<()> {
+        self.out.write_all(b"FRAME\n")?;
+        for component in 0..3 {
+            let plane: Vec<u8> = canvas.iter().map(|&p| self.colours[(p & 0x3) as usize][component]).collect();
+            self.out.write_all(&plane)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// wraps another frontend and records everything it presents
+pub struct Recorder {
+    inner: Box<dyn Frontend>,
+    writer: Box<dyn VideoWriter>,
+    scale: usize,
+}
+
+impl Recorder {
+    pub fn new(inner: Box<dyn Frontend>, writer: Box<dyn VideoWriter>, scale: usize) -> Self {
+        Recorder { inner, writer, scale: scale.max(1) }
+    }
+
+    // record to a file, or to stdout if `path` is "-"
+    pub fn create(
+        inner: Box<dyn Frontend>,
+        path: &Path,
+        format: VideoFormat,
+        scale: usize,
+        palette: &Palette,
+    ) -> io::Result<Self> {
+        let out: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+
+        let writer: Box<dyn VideoWriter> = match format {
+            VideoFormat::Gif => Box::new(GifWriter::new(out, scale, palette)?),
+            VideoFormat::Y4m => Box::new(Y4mWriter::new(out, scale, palette)?),
+        };
+        Ok(Self::new(inner, writer, scale))
+    }
+}
+
+impl Frontend for Recorder {
+    fn poll(&mut self, cpu: &mut CPU) -> io::Result<bool> {
+        self.inner.poll(cpu)
+    }
+
+    fn present(&mut self, cpu: &CPU) -> io::Result<()> {
+        self.inner.present(cpu)?;
+        self.writer.frame(&canvas(cpu.display(), self.scale))
+    }
+
+    // finish both even if the first fails so the terminal still gets restored
+    fn finish(&mut self, cpu: &CPU) -> io::Result<()> {
+        let recorded = self.writer.finish();
+        self.inner.finish(cpu)?;
+        recorded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{HEIGHT, WIDTH};
+
+    const SIZE: usize = HIRES_WIDTH * HIRES_HEIGHT;
+
+    #[test]
+    fn formats() {
+        assert_eq!(VideoFormat::from_path(Path::new("run.GIF")), Some(VideoFormat::Gif));
+        assert_eq!(VideoFormat::from_path(Path::new("run.y4m")), Some(VideoFormat::Y4m));
+        assert_eq!(VideoFormat::from_path(Path::new("run.mp4")), None);
+        assert_eq!(VideoFormat::from_path(Path::new("run")), None);
+        assert_eq!("webm".parse::<VideoFormat>(), Err("unknown video format 'webm'".to_string()));
+    }
+
+    #[test]
+    fn lores_is_doubled() {
+        let mut display = Display::new();
+        display.draw_sprite(WIDTH - 1, HEIGHT - 1, &[0x80], false);
+        let data = canvas(&display, 1);
+        assert_eq!(data.len(), SIZE);
+        assert_eq!(data.iter().filter(|&&p| p != 0).count(), 4);
+        assert_eq!(data[SIZE - 1], 1);
+        assert_eq!(data[SIZE - HIRES_WIDTH - 2], 1);
+
+        display.set_hires(true);
+        display.draw_sprite(0, 0, &[0x80], false);
+        let data = canvas(&display, 2);
+        assert_eq!(data.len(), SIZE * 4);
+        assert_eq!(data.iter().filter(|&&p| p != 0).count(), 4);
+    }
+
+    #[test]
+    fn y4m() {
+        let mut out = Vec::new();
+        let mut writer = Y4mWriter::new(&mut out, 1, &Palette::default()).unwrap();
+        let mut frame = vec![0; SIZE];
+        frame[0] = 1;
+        writer.frame(&frame).unwrap();
+        writer.frame(&frame).unwrap();
+        writer.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+        assert_eq!(out[..header.len()], header[..]);
+        let frames = &out[header.len()..];
+        assert_eq!(frames.len(), 2 * (6 + 3 * SIZE));
+        assert_eq!(frames[..6], b"FRAME\n"[..]);
+        // white then black in each of Y, Cb and Cr
+        assert_eq!(frames[6..8], [235, 16]);
+        assert_eq!(frames[6 + SIZE..][..2], [128, 128]);
+        assert_eq!(frames[6 + 2 * SIZE..][..2], [128, 128]);
+    }
+
+    #[test]
+    fn gif_merges_unchanged_frames() {
+        let blank = vec![0; SIZE];
+        let mut dot = blank.clone();
+        dot[HIRES_WIDTH + 1] = 3;
+        let mut out = Vec::new();
+        {
+            let mut writer = GifWriter::new(&mut out, 1, &Palette::default()).unwrap();
+            for frame in [&blank, &blank, &blank, &dot] {
+                writer.frame(frame).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(out.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 64));
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer.to_vec()));
+        }
+        // three frames at 60 Hz round to 5 hundredths, the fourth ends at 7
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].0, frames[1].0), (5, 2));
+        assert_eq!(frames[0].1, blank);
+        assert_eq!(frames[1].1, dot);
+    }
+}
@@ -0,0 +1,378 @@
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use pretty_hex::{config_hex, HexConfig};
+
+use crate::cpu::CpuError;
+use crate::debugger::{Debugger, Stop};
+use crate::instruction::Instruction;
+
+const HELP: &str = "\
+commands:
+    s, step [n]          run n instructions (default 1)
+    n, next              step, running calls through to their return
+    c, continue          run until a breakpoint or the program exits
+    b, break <addr>      set a breakpoint
+    d, delete [addr]     remove a breakpoint, or all of them
+    info b               list breakpoints
+    r, regs              show registers, timers and the stack
+    x/<n> [addr]         dump n bytes (default 16) from addr (default I)
+    x/<n>i [addr]        show n instructions from addr (default pc)
+    set <reg> <value>    set v0-vf, i, pc, dt or st
+    poke <addr> <byte>.. write bytes to memory
+    press <key>          press a keypad key, 0-f
+    release <key>        release a keypad key
+    screen               show the display
+    q, quit              leave
+addresses and values are decimal or hex with a 0x or # prefix, pc and i can
+be used as addresses. an empty line repeats the last command";
+
+// `rschip8 debug`, a gdb-ish prompt. `interrupted` is set from outside
+// (by a ctrl-c handler) to stop a continue
+pub struct Repl<'a, W: Write> {
+    debugger: Debugger,
+    out: W,
+    interrupted: &'a AtomicBool,
+}
+
+// decimal, or hex with a 0x or # prefix
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).or_else(|| s.strip_prefix('#')) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_u8(s: &str) -> Result<u8, String> {
+    parse_number(s)
+        .and_then(|n| u8::try_from(n).ok())
+        .ok_or(format!("invalid byte '{}'", s))
+}
+
+fn parse_key(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(s, 16)
+        .ok()
+        .filter(|&k| k < 16)
+        .ok_or(format!("invalid key '{}'", s))
+}
+
+impl<'a, W: Write> Repl<'a, W> {
+    pub fn new(debugger: Debugger, out: W, interrupted: &'a AtomicBool) -> Self {
+        Repl { debugger, out, interrupted }
+    }
+
+    // read commands from `input` until it runs out or we're told to quit
+    pub fn run(&mut self, input: impl BufRead) -> io::Result<()> {
+        let mut lines = input.lines();
+        let mut last = String::new();
+
+        self.show_location()?;
+        loop {
+            write!(self.out, "(rschip8) ")?;
+            self.out.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            let line = match line.trim() {
+                "" => last.clone(),
+                line => line.to_string(),
+            };
+            last = line.clone();
+
+            match self.command(&line) {
+                Ok(true) => {},
+                Ok(false) => break,
+                Err(e) => writeln!(self.out, "{}", e)?,
+            }
+        }
+
+        writeln!(self.out)
+    }
+
+    // returns false to quit
+    fn command(&mut self, line: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(true),
+        };
+
+        if let Some(format) = command.strip_prefix("x/") {
+            self.examine(format, args.first().copied())?;
+            return Ok(true);
+        }
+
+        match (command, args) {
+            ("s" | "step", []) => self.resume(|debugger, _| debugger.step())?,
+            ("s" | "step", [n]) => {
+                let n = parse_number(n).ok_or(format!("invalid count '{}'", n))?;
+                self.resume(|debugger, _| {
+                    let mut stop = Stop::Step;
+                    for _ in 0..n {
+                        stop = debugger.step()?;
+                        if stop != Stop::Step {
+                            break;
+                        }
+                    }
+                    Ok(stop)
+                })?;
+            },
+            ("n" | "next", []) => self.resume(|debugger, interrupted| debugger.next(interrupted))?,
+            ("c" | "continue", []) => self.resume(|debugger, interrupted| debugger.cont(interrupted))?,
+            ("b" | "break", [addr]) => {
+                let addr = self.address(addr)?;
+                if self.debugger.add_breakpoint(addr) {
+                    writeln!(self.out, "breakpoint at {:#06x}", addr)?;
+                } else {
+                    writeln!(self.out, "already a breakpoint at {:#06x}", addr)?;
+                }
+            },
+            ("d" | "delete", []) => self.debugger.clear_breakpoints(),
+            ("d" | "delete", [addr]) => {
+                let addr = self.address(addr)?;
+                if !self.debugger.remove_breakpoint(addr) {
+                    writeln!(self.out, "no breakpoint at {:#06x}", addr)?;
+                }
+            },
+            ("info", ["b" | "break" | "breakpoints"]) => {
+                let breakpoints: Vec<u16> = self.debugger.breakpoints().collect();
+                if breakpoints.is_empty() {
+                    writeln!(self.out, "no breakpoints")?;
+                }
+                for addr in breakpoints {
+                    writeln!(self.out, "{:#06x}", addr)?;
+                }
+            },
+            ("r" | "regs", []) | ("info", ["r" | "registers"]) => self.show_registers()?,
+            ("set", [reg, value]) => self.set(reg, value)?,
+            ("poke", [addr, bytes @ ..]) if !bytes.is_empty() => {
+                let addr = self.address(addr)?;
+                let bytes = bytes.iter().map(|b| parse_u8(b)).collect::<Result<Vec<u8>, _>>()?;
+                self.debugger.cpu_mut().write_bytes(addr, &bytes)?;
+            },
+            ("press", [key]) => self.debugger.cpu_mut().press_key(parse_key(key)?),
+            ("release", [key]) => self.debugger.cpu_mut().release_key(parse_key(key)?),
+            ("screen", []) => self.show_screen()?,
+            ("h" | "help", []) => writeln!(self.out, "{}", HELP)?,
+            ("q" | "quit", []) => return Ok(false),
+            _ => writeln!(self.out, "unknown command '{}', try help", line)?,
+        }
+
+        Ok(true)
+    }
+
+    fn address(&self, s: &str) -> Result<u16, String> {
+        let cpu = self.debugger.cpu();
+        match s {
+            "pc" => Ok(cpu.get_pc()),
+            "i" => Ok(cpu.get_i()),
+            _ => parse_number(s)
+                .and_then(|n| u16::try_from(n).ok())
+                .ok_or(format!("invalid address '{}'", s)),
+        }
+    }
+
+    // run something that moves the program along and say where it stopped
+    fn resume(
+        &mut self,
+        f: impl FnOnce(&mut Debugger, &mut dyn FnMut() -> bool) -> Result<Stop, CpuError>,
+    ) -> io::Result<()> {
+        let flag = self.interrupted;
+        flag.store(false, Ordering::SeqCst);
+        let mut interrupted = || flag.load(Ordering::SeqCst);
+
+        match f(&mut self.debugger, &mut interrupted) {
+            Ok(Stop::Step) => {},
+            Ok(Stop::Breakpoint) => writeln!(self.out, "breakpoint")?,
+            Ok(Stop::Exited) => writeln!(self.out, "program exited")?,
+            Ok(Stop::Interrupted) => writeln!(self.out, "interrupted")?,
+            Err(e) => writeln!(self.out, "error: {}", e)?,
+        }
+        self.show_location()
+    }
+
+    fn show_location(&mut self) -> io::Result<()> {
+        let pc = self.debugger.cpu().get_pc();
+        self.show_instructions(pc, 1)?;
+        if self.debugger.cpu().is_waiting_for_key() {
+            writeln!(self.out, "waiting for a key, use press and release")?;
+        }
+        Ok(())
+    }
+
+    fn show_instructions(&mut self, mut addr: u16, count: u32) -> io::Result<()> {
+        let cpu = self.debugger.cpu();
+        for _ in 0..count {
+            let opcode = match cpu.read_word(addr) {
+                Ok(opcode) => opcode,
+                Err(_) => break,
+            };
+            let next = cpu.read_word(addr.wrapping_add(2)).unwrap_or(0);
+            let marker = if addr == cpu.get_pc() { "=>" } else { "  " };
+
+            match Instruction::decode_long(opcode, next) {
+                Ok(insn) => {
+                    let bytes: String = insn.to_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+                    writeln!(self.out, "{} {:#06x}: {:<8}  {}", marker, addr, bytes, insn)?;
+                    addr = addr.wrapping_add(insn.size());
+                },
+                Err(_) => {
+                    writeln!(self.out, "{} {:#06x}: {:04x}      ???", marker, addr, opcode)?;
+                    addr = addr.wrapping_add(2);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn examine(&mut self, format: &str, addr: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+        let (count, instructions) = match format.strip_suffix('i') {
+            Some(count) => (count, true),
+            None => (format.strip_suffix('b').unwrap_or(format), false),
+        };
+        let count = match count {
+            "" => if instructions { 1 } else { 16 },
+            count => parse_number(count).ok_or(format!("invalid count '{}'", count))?,
+        };
+        let default = if instructions { "pc" } else { "i" };
+        let addr = self.address(addr.unwrap_or(default))?;
+
+        if instructions {
+            self.show_instructions(addr, count)?;
+            return Ok(());
+        }
+
+        // only dump what's actually there
+        let size = self.debugger.cpu().memory_size();
+        let count = (count as usize).min(size.saturating_sub(addr as usize));
+        let bytes = self.debugger.cpu().read_bytes(addr, count)?;
+
+        let config = HexConfig { title: false, width: 16, group: 0, ..HexConfig::default() };
+        for (row, chunk) in bytes.chunks(16).enumerate() {
+            // pretty_hex counts from zero, put the real address in instead
+            let line = config_hex(&chunk, config);
+            let line = line.trim_start_matches("0000:");
+            writeln!(self.out, "{:04x}:{}", addr as usize + row * 16, line)?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, reg: &str, value: &str) -> Result<(), String> {
+        let value = parse_number(value).ok_or(format!("invalid value '{}'", value))?;
+        let byte = || u8::try_from(value).map_err(|_| format!("{} doesn't fit in a byte", value));
+        let word = || u16::try_from(value).map_err(|_| format!("{} doesn't fit in 16 bits", value));
+        let cpu = self.debugger.cpu_mut();
+
+        match reg.to_ascii_lowercase().as_str() {
+            "i" => cpu.set_i(word()?),
+            "pc" => cpu.go(word()?),
+            "dt" => cpu.set_delay_timer(byte()?),
+            "st" => cpu.set_sound_timer(byte()?),
+            reg => {
+                let x = reg.strip_prefix('v')
+                    .filter(|x| x.len() == 1)
+                    .and_then(|x| u8::from_str_radix(x, 16).ok())
+                    .ok_or(format!("unknown register '{}'", reg))?;
+                cpu.set_v(x, byte()?);
+            },
+        }
+        Ok(())
+    }
+
+    fn show_registers(&mut self) -> io::Result<()> {
+        let cpu = self.debugger.cpu();
+        for row in 0..2 {
+            let regs: Vec<String> = (row * 8..row * 8 + 8)
+                .map(|x| format!("V{:X} {:02x}", x, cpu.get_v(x)))
+                .collect();
+            writeln!(self.out, "{}", regs.join("  "))?;
+        }
+        writeln!(
+            self.out,
+            "I  {:04x}  PC {:04x}  DT {:02x}  ST {:02x}",
+            cpu.get_i(), cpu.get_pc(), cpu.get_delay_timer(), cpu.get_sound_timer(),
+        )?;
+
+        let stack: Vec<String> = cpu.stack().entries().iter().map(|addr| format!("{:#06x}", addr)).collect();
+        writeln!(self.out, "stack ({}): {}", stack.len(), stack.join(" "))
+    }
+
+    fn show_screen(&mut self) -> io::Result<()> {
+        let display = self.debugger.cpu().display();
+        for y in 0..display.height() {
+            let row: String = (0..display.width())
+                .map(|x| if display.get(x, y) { '#' } else { '.' })
+                .collect();
+            writeln!(self.out, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{CPU, PROGRAM_START};
+
+    // feed `input` to a repl over LD V0,0x2a, LD I,0x20a, CALL 0x208, EXIT,
+    // RET, and return everything it printed
+    fn session(input: &str) -> String {
+        let mut cpu = CPU::new();
+        cpu.load_rom(PROGRAM_START, &[0x60, 0x2a, 0xa2, 0x0a, 0x22, 0x08, 0x00, 0xfd, 0x00, 0xee]).unwrap();
+        let interrupted = AtomicBool::new(false);
+        let mut out = Vec::new();
+        Repl::new(Debugger::new(cpu), &mut out, &interrupted).run(input.as_bytes()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("0x2a"), Some(42));
+        assert_eq!(parse_number("0X2A"), Some(42));
+        assert_eq!(parse_number("#2a"), Some(42));
+        assert_eq!(parse_number("2a"), None);
+        assert_eq!(parse_u8("#ff"), Ok(0xff));
+        assert!(parse_u8("256").is_err());
+        assert_eq!(parse_key("f"), Ok(0xf));
+        assert!(parse_key("10").is_err());
+    }
+
+    #[test]
+    fn stepping_and_breakpoints() {
+        let out = session("b 0x208\nb #208\nc\nr\n\nc\nq\n");
+        assert!(out.starts_with("=> 0x0200: 602a"), "{}", out);
+        assert!(out.contains("breakpoint at 0x0208\n"), "{}", out);
+        assert!(out.contains("already a breakpoint at 0x0208\n"), "{}", out);
+        assert!(out.contains("breakpoint\n=> 0x0208: 00ee"), "{}", out);
+        assert!(out.contains("V0 2a  V1 00"), "{}", out);
+        assert!(out.contains("I  020a  PC 0208"), "{}", out);
+        assert!(out.contains("stack (1): 0x0206"), "{}", out);
+        // the empty line repeated regs
+        assert_eq!(out.matches("stack (1)").count(), 2, "{}", out);
+        assert!(out.contains("program exited\n"), "{}", out);
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let out = session("set v3 #ff\nset i 0x20a\npoke i 1 2\nx/3\nr\nx/2i 0x200\nset v3 256\nset vg 1\n");
+        assert!(out.contains("V3 ff"), "{}", out);
+        assert!(out.contains("020a:   01 02 00"), "{}", out);
+        assert!(out.contains("=> 0x0200: 602a"), "{}", out);
+        assert!(out.contains("   0x0202: a20a"), "{}", out);
+        assert!(out.contains("256 doesn't fit in a byte\n"), "{}", out);
+        assert!(out.contains("unknown register 'vg'\n"), "{}", out);
+    }
+
+    #[test]
+    fn bad_commands() {
+        let out = session("frob\nb nowhere\nd 0x300\ninfo b\n");
+        assert!(out.contains("unknown command 'frob', try help\n"), "{}", out);
+        assert!(out.contains("invalid address 'nowhere'\n"), "{}", out);
+        assert!(out.contains("no breakpoint at 0x0300\n"), "{}", out);
+        assert!(out.contains("no breakpoints\n"), "{}", out);
+    }
+}
@@ -0,0 +1,163 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cpu::{CpuError, CPU, TIMER_HZ};
+use crate::frontend::Frontend;
+
+#[derive(Debug)]
+pub enum RunError {
+    Cpu(CpuError),
+    Io(io::Error),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Cpu(e) => write!(f, "{}", e),
+            RunError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for RunError {}
+
+impl From<CpuError> for RunError {
+    fn from(e: CpuError) -> Self {
+        RunError::Cpu(e)
+    }
+}
+
+impl From<io::Error> for RunError {
+    fn from(e: io::Error) -> Self {
+        RunError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pace {
+    // one frame every 1/60th of a second
+    RealTime,
+    // as fast as the host can go
+    Unthrottled,
+}
+
+// run frames until the frontend asks to stop, the program exits or
+// `max_frames` have been run, returning the number of frames run
+pub fn run(
+    cpu: &mut CPU,
+    frontend: &mut dyn Frontend,
+    pace: Pace,
+    max_frames: Option<u64>,
+) -> Result<u64, RunError> {
+    let frame_time = Duration::from_secs(1) / TIMER_HZ;
+    let mut next_frame = Instant::now();
+    let mut frames = 0;
+
+    let result = loop {
+        if cpu.has_exited() || max_frames.is_some_and(|max| frames >= max) {
+            break Ok(frames);
+        }
+
+        match frontend.poll(cpu) {
+            Ok(true) => {},
+            Ok(false) => break Ok(frames),
+            Err(e) => break Err(e.into()),
+        }
+
+        if let Err(e) = cpu.run_frame() {
+            break Err(e.into());
+        }
+        frames += 1;
+
+        if let Err(e) = frontend.present(cpu) {
+            break Err(e.into());
+        }
+
+        if pace == Pace::RealTime {
+            next_frame += frame_time;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                // don't try and catch up if we've fallen behind
+                next_frame = now;
+            }
+        }
+    };
+
+    // always give the frontend a chance to clean up, even on error
+    frontend.finish(cpu)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // counts calls, asking to stop after `stop_after` polls
+    #[derive(Default)]
+    struct Counting {
+        polls: u64,
+        presents: u64,
+        finished: bool,
+        stop_after: Option<u64>,
+    }
+
+    impl Frontend for Counting {
+        fn poll(&mut self, _cpu: &mut CPU) -> io::Result<bool> {
+            self.polls += 1;
+            Ok(self.stop_after.is_none_or(|stop| self.polls <= stop))
+        }
+
+        fn present(&mut self, _cpu: &CPU) -> io::Result<()> {
+            self.presents += 1;
+            Ok(())
+        }
+
+        fn finish(&mut self, _cpu: &CPU) -> io::Result<()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    fn cpu(rom: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load_rom(crate::cpu::PROGRAM_START, rom).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn max_frames() {
+        // JP 0x200 forever
+        let mut frontend = Counting::default();
+        assert_eq!(run(&mut cpu(&[0x12, 0x00]), &mut frontend, Pace::Unthrottled, Some(5)).unwrap(), 5);
+        assert_eq!((frontend.polls, frontend.presents, frontend.finished), (5, 5, true));
+    }
+
+    #[test]
+    fn frontend_stops_it() {
+        let mut frontend = Counting { stop_after: Some(3), ..Counting::default() };
+        assert_eq!(run(&mut cpu(&[0x12, 0x00]), &mut frontend, Pace::Unthrottled, None).unwrap(), 3);
+        assert_eq!((frontend.polls, frontend.presents, frontend.finished), (4, 3, true));
+    }
+
+    #[test]
+    fn program_exits() {
+        let mut frontend = Counting::default();
+        assert_eq!(run(&mut cpu(&[0x00, 0xfd]), &mut frontend, Pace::Unthrottled, None).unwrap(), 1);
+        assert!(frontend.finished);
+    }
+
+    #[test]
+    fn errors_still_finish() {
+        // RET with nothing to return to
+        let mut frontend = Counting::default();
+        let result = run(&mut cpu(&[0x00, 0xee]), &mut frontend, Pace::Unthrottled, None);
+        assert!(matches!(result, Err(RunError::Cpu(CpuError::StackUnderflow))), "{:?}", result);
+        assert_eq!(frontend.presents, 0);
+        assert!(frontend.finished);
+    }
+}
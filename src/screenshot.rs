@@ -0,0 +1,183 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::display::Display;
+
+pub type Rgb = [u8; 3];
+
+// the colour for each pixel value, index 0 is the background. plain CHIP-8
+// only ever uses the first two, XO-CHIP uses all four
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colours: [Rgb; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colours: [[0x00, 0x00, 0x00], [0xff, 0xff, 0xff], [0xaa, 0xaa, 0xaa], [0x55, 0x55, 0x55]],
+        }
+    }
+}
+
+impl Palette {
+    pub fn colour(&self, pixel: u8) -> Rgb {
+        self.colours[(pixel & 0x3) as usize]
+    }
+}
+
+// two to four comma separated hex colours like "000000,ffffff"
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut palette = Palette::default();
+        let parts: Vec<&str> = s.split(',').collect();
+        if parts.len() < 2 || parts.len() > 4 {
+            return Err(format!("palette '{}' should have 2 to 4 colours", s));
+        }
+
+        for (colour, part) in palette.colours.iter_mut().zip(parts) {
+            let part = part.trim().trim_start_matches('#');
+            let value = u32::from_str_radix(part, 16)
+                .ok()
+                .filter(|_| part.len() == 6)
+                .ok_or(format!("invalid colour '{}'", part))?;
+            let [_, r, g, b] = value.to_be_bytes();
+            *colour = [r, g, b];
+        }
+
+        Ok(palette)
+    }
+}
+
+// the display as 8 bit RGB, each pixel blown up to `scale` x `scale`.
+// returns (width, height, data)
+pub fn render(display: &Display, scale: usize, palette: &Palette) -> (usize, usize, Vec<u8>) {
+    let width = display.width() * scale;
+    let height = display.height() * scale;
+    let mut data = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        for x in 0..width {
+            data.extend_from_slice(&palette.colour(display.pixel(x / scale, y / scale)));
+        }
+    }
+
+    (width, height, data)
+}
+
+pub fn write_png(w: impl Write, display: &Display, scale: usize, palette: &Palette) -> io::Result<()> {
+    let (width, height, data) = render(display, scale, palette);
+
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+pub fn save_png(path: &Path, display: &Display, scale: usize, palette: &Palette) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    write_png(file, display, scale, palette)
+}
+
+// "shot.png" becomes "shot-000060.png" for frame 60
+pub fn numbered_path(path: &Path, n: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{:06}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{:06}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+// saves the final frame to `path` and, if `every` is set, every nth frame
+// along the way to a numbered file next to it
+#[derive(Debug, Clone)]
+pub struct Screenshot {
+    pub path: PathBuf,
+    pub scale: usize,
+    pub palette: Palette,
+    pub every: Option<u64>,
+}
+
+impl Screenshot {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Screenshot {
+            path: path.into(),
+            scale: 1,
+            palette: Palette::default(),
+            every: None,
+        }
+    }
+
+    // call after frame number `frame` (counting from 1) has run
+    pub fn frame(&self, display: &Display, frame: u64) -> io::Result<()> {
+        match self.every {
+            Some(every) if frame.is_multiple_of(every) =>
+                save_png(&numbered_path(&self.path, frame), display, self.scale, &self.palette),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn finish(&self, display: &Display) -> io::Result<()> {
+        save_png(&self.path, display, self.scale, &self.palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes() {
+        let palette: Palette = "#102030, 405060".parse().unwrap();
+        assert_eq!(palette.colours[..2], [[0x10, 0x20, 0x30], [0x40, 0x50, 0x60]]);
+        // the rest keep their defaults
+        assert_eq!(palette.colours[2..], Palette::default().colours[2..]);
+        assert_eq!(palette.colour(5), [0x40, 0x50, 0x60]);
+
+        assert!("000000".parse::<Palette>().is_err());
+        assert!("000000,ffffff,000000,ffffff,000000".parse::<Palette>().is_err());
+        assert_eq!("000000,fff".parse::<Palette>(), Err("invalid colour 'fff'".to_string()));
+        assert_eq!("000000,gggggg".parse::<Palette>(), Err("invalid colour 'gggggg'".to_string()));
+    }
+
+    #[test]
+    fn renders_scaled() {
+        let mut display = Display::new();
+        display.draw_sprite(1, 0, &[0x80], false);
+        let (width, height, data) = render(&display, 2, &Palette::default());
+        assert_eq!((width, height, data.len()), (128, 64, 128 * 64 * 3));
+
+        // pixel (1, 0) covers x 2-3 on the first two rows
+        let lit = |x: usize, y: usize| data[(y * width + x) * 3..][..3] == [0xff; 3];
+        assert_eq!((0..5).map(|x| lit(x, 0)).collect::<Vec<_>>(), [false, false, true, true, false]);
+        assert!(lit(2, 1) && lit(3, 1) && !lit(2, 2));
+    }
+
+    #[test]
+    fn png() {
+        let mut display = Display::new();
+        display.draw_sprite(0, 0, &[0x80], false);
+        let mut out = Vec::new();
+        write_png(&mut out, &display, 3, &Palette::default()).unwrap();
+
+        let mut reader = png::Decoder::new(out.as_slice()).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (192, 96, png::ColorType::Rgb));
+        assert_eq!(data[..12], [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0]);
+    }
+
+    #[test]
+    fn numbered_paths() {
+        assert_eq!(numbered_path(Path::new("out/shot.png"), 60), Path::new("out/shot-000060.png"));
+        assert_eq!(numbered_path(Path::new("shot"), 1234567), Path::new("shot-1234567"));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::cpu::CpuError;
+
+pub const VIP_STACK_DEPTH: usize = 12;
+pub const SCHIP_STACK_DEPTH: usize = 16;
+
+// the VIP keeps its stack in the 0xEA0..0xED0 area, growing down from the top
+pub const VIP_STACK_TOP: u16 = 0xed0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackDepth {
+    Limited(usize),
+    // handy for testing, only runs out when the host does
+    Unlimited,
+}
+
+// return addresses for 2NNN/00EE, kept separately from main memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stack {
+    entries: Vec<u16>,
+    depth: StackDepth,
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new(StackDepth::Limited(VIP_STACK_DEPTH))
+    }
+}
+
+impl Stack {
+    pub fn new(depth: StackDepth) -> Self {
+        Stack {
+            entries: Vec::new(),
+            depth,
+        }
+    }
+
+    pub fn depth(&self) -> StackDepth {
+        self.depth
+    }
+
+    pub fn set_depth(&mut self, depth: StackDepth) {
+        self.depth = depth;
+    }
+
+    pub fn push(&mut self, addr: u16) -> Result<(), CpuError> {
+        if let StackDepth::Limited(max) = self.depth {
+            if self.entries.len() >= max {
+                return Err(CpuError::StackOverflow);
+            }
+        }
+
+        self.entries.push(addr);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u16, CpuError> {
+        self.entries.pop().ok_or(CpuError::StackUnderflow)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // oldest entry first
+    pub fn entries(&self) -> &[u16] {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limited() {
+        let mut stack = Stack::new(StackDepth::Limited(2));
+        assert_eq!(stack.pop(), Err(CpuError::StackUnderflow));
+        stack.push(0x202).unwrap();
+        stack.push(0x304).unwrap();
+        assert_eq!(stack.push(0x406), Err(CpuError::StackOverflow));
+        assert_eq!(stack.entries(), [0x202, 0x304]);
+
+        assert_eq!(stack.pop(), Ok(0x304));
+        assert_eq!(stack.pop(), Ok(0x202));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn unlimited() {
+        let mut stack = Stack::new(StackDepth::Unlimited);
+        (0..1000).for_each(|addr| stack.push(addr).unwrap());
+        assert_eq!(stack.len(), 1000);
+        stack.clear();
+        assert_eq!(stack.pop(), Err(CpuError::StackUnderflow));
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// where the symbols for a ROM live, next to it as <name>.sym
+pub fn path_for_rom(rom: &Path) -> PathBuf {
+    rom.with_extension("sym")
+}
+
+// which source line an instruction came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineEntry {
+    pub addr: u16,
+    pub file: String,
+    pub line: u32,
+}
+
+// labels and line numbers for a ROM, written by the assembler and read by
+// the debuggers. as text it's one entry per line:
+//
+//   label <name> <addr>
+//   line <addr> <line> <file>
+//
+// with addresses in hex. blank lines and lines starting with # are ignored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    labels: BTreeMap<String, u16>,
+    lines: Vec<LineEntry>,
+}
+
+impl SymbolMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_label(&mut self, name: &str, addr: u16) {
+        self.labels.insert(name.to_string(), addr);
+    }
+
+    pub fn add_line(&mut self, addr: u16, file: &str, line: u32) {
+        self.lines.push(LineEntry { addr, file: file.to_string(), line });
+    }
+
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.get(name).copied()
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.labels.iter().map(|(name, addr)| (name.as_str(), *addr))
+    }
+
+    pub fn lines(&self) -> &[LineEntry] {
+        &self.lines
+    }
+
+    // the source line for the instruction at `addr`
+    pub fn line_for_address(&self, addr: u16) -> Option<&LineEntry> {
+        self.lines.iter().find(|entry| entry.addr == addr)
+    }
+
+    // where to break for a line in `file`. lines without code (comments,
+    // labels) move down to the next one that has some, which is also
+    // returned
+    pub fn address_for_line(&self, file: &str, line: u32) -> Option<&LineEntry> {
+        self.lines
+            .iter()
+            .filter(|entry| entry.file == file && entry.line >= line)
+            .min_by_key(|entry| (entry.line, entry.addr))
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut map = SymbolMap::new();
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || format!("line {}: invalid symbol entry '{}'", n + 1, line);
+            let addr = |s: &str| u16::from_str_radix(s, 16).map_err(|_| bad());
+
+            let mut fields = line.splitn(4, ' ');
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some("label"), Some(name), Some(value), None) => map.add_label(name, addr(value)?),
+                (Some("line"), Some(value), Some(number), Some(file)) => {
+                    let number = number.parse().map_err(|_| bad())?;
+                    map.add_line(addr(value)?, file, number);
+                },
+                _ => return Err(bad()),
+            }
+        }
+
+        Ok(map)
+    }
+
+    // relative source paths are taken to be relative to the symbol file
+    // rather than wherever we happen to be running from
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut map = Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for entry in &mut map.lines {
+            if Path::new(&entry.file).is_relative() {
+                entry.file = dir.join(&entry.file).to_string_lossy().into_owned();
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for SymbolMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, addr) in &self.labels {
+            writeln!(f, "label {} {:04x}", name, addr)?;
+        }
+        for entry in &self.lines {
+            writeln!(f, "line {:04x} {} {}", entry.addr, entry.line, entry.file)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::io::{self, Stdout, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+    KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, queue, style, terminal};
+
+use crate::cpu::CPU;
+use crate::display::Display;
+use crate::frontend::Frontend;
+
+// most terminals only tell us about key presses, so a key is held for this
+// many frames after the last press (or auto-repeat) we saw for it
+const KEY_HOLD_FRAMES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    // 1x2 pixels per character cell using ▀ ▄ █
+    HalfBlock,
+    // 2x4 pixels per character cell using braille patterns, for small
+    // terminals
+    Braille,
+}
+
+impl RenderMode {
+    // pixels per character cell
+    fn cell_size(&self) -> (usize, usize) {
+        match self {
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+        }
+    }
+
+    fn cell(&self, display: &Display, cx: usize, cy: usize) -> char {
+        let (cw, ch) = self.cell_size();
+        let lit = |dx: usize, dy: usize| {
+            let (x, y) = (cx * cw + dx, cy * ch + dy);
+            x < display.width() && y < display.height() && display.get(x, y)
+        };
+
+        match self {
+            RenderMode::HalfBlock => match (lit(0, 0), lit(0, 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            },
+            RenderMode::Braille => {
+                // dot numbering goes down the left column then the right,
+                // with the bottom row added later as dots 7 and 8
+                const DOTS: [(usize, usize, u32); 8] = [
+                    (0, 0, 0x01), (0, 1, 0x02), (0, 2, 0x04), (1, 0, 0x08),
+                    (1, 1, 0x10), (1, 2, 0x20), (0, 3, 0x40), (1, 3, 0x80),
+                ];
+                let bits = DOTS.iter()
+                    .filter(|(dx, dy, _)| lit(*dx, *dy))
+                    .fold(0, |bits, (_, _, bit)| bits | bit);
+                std::char::from_u32(0x2800 + bits).unwrap()
+            },
+        }
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "halfblock" => Ok(RenderMode::HalfBlock),
+            "braille" => Ok(RenderMode::Braille),
+            _ => Err(format!("unknown render mode '{}'", s)),
+        }
+    }
+}
+
+// which keyboard key drives which keypad key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    keys: HashMap<char, u8>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::qwerty()
+    }
+}
+
+impl KeyMap {
+    // the usual layout, the left hand side of a QWERTY keyboard laid out
+    // like the VIP's keypad:
+    //
+    //   1 2 3 4      1 2 3 C
+    //   q w e r  ->  4 5 6 D
+    //   a s d f      7 8 9 E
+    //   z x c v      A 0 B F
+    pub fn qwerty() -> Self {
+        "x123qweasdzc4rfv".parse().unwrap()
+    }
+
+    pub fn key(&self, c: char) -> Option<u8> {
+        self.keys.get(&c.to_ascii_lowercase()).copied()
+    }
+}
+
+// sixteen characters, the keyboard keys for keypad keys 0 to F in order
+impl FromStr for KeyMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().map(|c| c.to_ascii_lowercase()).collect();
+        if chars.len() != 16 {
+            return Err(format!("key map '{}' should have 16 keys, one for each of 0-F", s));
+        }
+
+        let keys: HashMap<char, u8> = chars.iter().enumerate().map(|(k, &c)| (c, k as u8)).collect();
+        if keys.len() != 16 {
+            return Err(format!("key map '{}' uses a key more than once", s));
+        }
+
+        Ok(KeyMap { keys })
+    }
+}
+
+// draws the display with unicode characters on an ANSI terminal, which
+// works fine over SSH
+pub struct Terminal {
+    out: Stdout,
+    mode: RenderMode,
+    keymap: KeyMap,
+    // what's currently on screen, so only changed cells get redrawn
+    cells: Vec<char>,
+    cells_width: usize,
+    // frames left before each key is released
+    held: [u32; 16],
+    // true if the terminal reports key releases so we don't need `held`
+    releases: bool,
+    sounding: bool,
+    // false once the terminal has been put back how we found it
+    active: bool,
+}
+
+impl Terminal {
+    pub fn new(mode: RenderMode, keymap: KeyMap) -> io::Result<Self> {
+        let mut out = io::stdout();
+
+        terminal::enable_raw_mode()?;
+        queue!(out, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            queue!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        out.flush()?;
+
+        Ok(Terminal {
+            out,
+            mode,
+            keymap,
+            cells: Vec::new(),
+            cells_width: 0,
+            held: [0; 16],
+            releases,
+            sounding: false,
+            active: true,
+        })
+    }
+
+    fn restore(&mut self) -> io::Result<()> {
+        if !self.active {
+            return Ok(());
+        }
+        self.active = false;
+
+        if self.releases {
+            queue!(self.out, PopKeyboardEnhancementFlags)?;
+        }
+        queue!(self.out, cursor::Show, terminal::LeaveAlternateScreen)?;
+        self.out.flush()?;
+        terminal::disable_raw_mode()
+    }
+
+    // returns false if the key means we should quit
+    fn handle_key(&mut self, cpu: &mut CPU, key: KeyEvent) -> bool {
+        let quit = key.code == KeyCode::Esc
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+        if quit {
+            return false;
+        }
+
+        let k = match key.code {
+            KeyCode::Char(c) => self.keymap.key(c),
+            _ => None,
+        };
+
+        if let Some(k) = k {
+            match key.kind {
+                KeyEventKind::Press | KeyEventKind::Repeat => {
+                    cpu.press_key(k);
+                    self.held[k as usize] = KEY_HOLD_FRAMES;
+                },
+                KeyEventKind::Release => {
+                    cpu.release_key(k);
+                    self.held[k as usize] = 0;
+                },
+            }
+        }
+        true
+    }
+}
+
+// don't leave the terminal in raw mode if we never got to finish
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+impl Frontend for Terminal {
+    fn poll(&mut self, cpu: &mut CPU) -> io::Result<bool> {
+        if !self.releases {
+            for k in 0..16 {
+                if self.held[k] > 0 {
+                    self.held[k] -= 1;
+                    if self.held[k] == 0 {
+                        cpu.release_key(k as u8);
+                    }
+                }
+            }
+        }
+
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if !self.handle_key(cpu, key) {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn present(&mut self, cpu: &CPU) -> io::Result<()> {
+        let display = cpu.display();
+        let (cw, ch) = self.mode.cell_size();
+        let width = display.width().div_ceil(cw);
+        let height = display.height().div_ceil(ch);
+
+        // start from scratch whenever the resolution changes
+        if width != self.cells_width || width * height != self.cells.len() {
+            queue!(self.out, terminal::Clear(terminal::ClearType::All))?;
+            self.cells = vec!['\0'; width * height];
+            self.cells_width = width;
+        }
+
+        for cy in 0..height {
+            for cx in 0..width {
+                let c = self.mode.cell(display, cx, cy);
+                let old = &mut self.cells[cy * width + cx];
+                if *old != c {
+                    *old = c;
+                    queue!(self.out, cursor::MoveTo(cx as u16, cy as u16), style::Print(c))?;
+                }
+            }
+        }
+
+        // ring the bell as the buzzer starts
+        let sounding = cpu.buzzed_last_frame();
+        if sounding && !self.sounding {
+            queue!(self.out, style::Print('\x07'))?;
+        }
+        self.sounding = sounding;
+
+        self.out.flush()
+    }
+
+    fn finish(&mut self, _cpu: &CPU) -> io::Result<()> {
+        self.restore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keymaps() {
+        let keymap = KeyMap::qwerty();
+        assert_eq!(keymap.key('x'), Some(0x0));
+        assert_eq!(keymap.key('1'), Some(0x1));
+        assert_eq!(keymap.key('4'), Some(0xc));
+        assert_eq!(keymap.key('R'), Some(0xd));
+        assert_eq!(keymap.key('v'), Some(0xf));
+        assert_eq!(keymap.key('p'), None);
+
+        let keymap: KeyMap = "0123456789ABCDEF".parse().unwrap();
+        assert_eq!(keymap.key('b'), Some(0xb));
+        assert!("0123".parse::<KeyMap>().is_err());
+        assert_eq!(
+            "0123456789abcdea".parse::<KeyMap>(),
+            Err("key map '0123456789abcdea' uses a key more than once".to_string()),
+        );
+    }
+
+    #[test]
+    fn half_blocks() {
+        let mut display = Display::new();
+        // (0, 0), (1, 1) and both of (2, 0) and (2, 1)
+        display.draw_sprite(0, 0, &[0xa0, 0x60], false);
+        let cells: String = (0..4).map(|cx| RenderMode::HalfBlock.cell(&display, cx, 0)).collect();
+        assert_eq!(cells, "▀▄█ ");
+    }
+
+    #[test]
+    fn braille() {
+        let mut display = Display::new();
+        display.draw_sprite(0, 0, &[0x80, 0x00, 0x00, 0x40], false);
+        assert_eq!(RenderMode::Braille.cell(&display, 0, 0), '⢁');
+        assert_eq!(RenderMode::Braille.cell(&display, 1, 0), '⠀');
+        // cells hanging off the edge are blank
+        assert_eq!(RenderMode::Braille.cell(&display, 32, 8), '⠀');
+
+        assert_eq!("braille".parse(), Ok(RenderMode::Braille));
+        assert_eq!("ascii".parse::<RenderMode>(), Err("unknown render mode 'ascii'".to_string()));
+    }
+}